@@ -3,7 +3,7 @@
 //! A CLI tool that allows AI agents to inject commands into interactive
 //! terminal sessions controlled by humans.
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use rand::Rng;
 use std::collections::HashSet;
 use std::env;
@@ -92,6 +92,85 @@ enum Commands {
         #[arg(long, default_value = "50")]
         last: usize,
 
+        /// Print output lines as they appear instead of waiting for the end
+        #[arg(long)]
+        stream: bool,
+
+        /// How to read output: pane scrollback, or a pipe-pane log of this run
+        #[arg(long, value_enum, default_value = "scrollback")]
+        capture: CaptureMode,
+
+        /// Replay the command's stderr on tb's stderr instead of stdout
+        #[arg(long)]
+        split_stderr: bool,
+
+        /// Forward tb's own stdin to the command (e.g. piped SQL or a patch)
+        #[arg(long)]
+        stdin: bool,
+
+        /// What the human sees in the pane (clean implies --capture pipe)
+        #[arg(long, value_enum, default_value = "raw")]
+        display: DisplayMode,
+
+        /// How to type the command into the pane
+        #[arg(long, value_enum, default_value = "auto")]
+        inject: InjectMode,
+
+        /// Fail immediately if another tb command is using the pane
+        #[arg(long)]
+        no_wait: bool,
+
+        /// Type the command even if the pane is not at an idle shell prompt
+        #[arg(long)]
+        force: bool,
+
+        /// Signals to escalate through on timeout, as SIGNAL:seconds steps
+        /// (e.g. INT:3,QUIT:3,TERM:5,KILL)
+        #[arg(long, default_value = "INT:3,QUIT:3", value_parser = parse_kill_sequence)]
+        kill_sequence: KillSequence,
+
+        /// What to do with a command that times out: kill it, or leave it
+        /// running as a task for `tb check` / `tb done`
+        #[arg(long, value_enum, default_value = "kill")]
+        on_timeout: OnTimeout,
+
+        /// Print one JSON object with the output, exit code and timing
+        #[arg(long, conflicts_with = "stream")]
+        json: bool,
+
+        /// How to treat colors and escape sequences in the output
+        #[arg(long, value_enum, default_value = "strip")]
+        ansi: AnsiMode,
+
+        /// Run the command in a subshell, so `cd`, `export` and `set` don't
+        /// change the human's shell
+        #[arg(long)]
+        isolate: bool,
+
+        /// Directory to run the command in (implies --isolate)
+        #[arg(long, value_name = "DIR")]
+        cwd: Option<String>,
+
+        /// Set an environment variable for this command only (repeatable;
+        /// implies --isolate)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+
+        /// Set an environment profile's variables for this command, e.g.
+        /// `noninteractive` to turn off pagers and prompts (needs --isolate)
+        #[arg(long, value_name = "NAME", requires = "isolate")]
+        profile: Option<String>,
+
+        /// Quit a pager the command opens (less, more) by typing `q`, and
+        /// keep waiting
+        #[arg(long)]
+        quit_pager: bool,
+
+        /// Fail when any stage of a pipeline fails, not just the last
+        /// (bash and fish report per-stage statuses)
+        #[arg(long)]
+        pipefail: bool,
+
         /// The command to run
         #[arg(last = true, required = true)]
         command: Vec<String>,
@@ -111,6 +190,14 @@ enum Commands {
         #[arg(short, long)]
         target: Option<String>,
 
+        /// Record the task pane with pipe-pane so `tb check` reads a full log
+        #[arg(long, value_enum, default_value = "scrollback")]
+        capture: CaptureMode,
+
+        /// Fail immediately if another tb launch is allocating a task here
+        #[arg(long)]
+        no_wait: bool,
+
         /// The command to run
         #[arg(last = true, required = true)]
         command: Vec<String>,
@@ -132,6 +219,70 @@ enum Commands {
         /// Lines to show from end of output
         #[arg(long, default_value = "50")]
         last: usize,
+
+        /// How to read output [default: the task's pipe-pane log if it has one]
+        #[arg(long, value_enum)]
+        capture: Option<CaptureMode>,
+
+        /// How to treat colors and escape sequences in the output
+        #[arg(long, value_enum, default_value = "strip")]
+        ansi: AnsiMode,
+    },
+
+    /// Wait for output in the main pane or a task
+    Wait {
+        /// Optional task ID (e.g., t1); omit to watch the main pane
+        task: Option<String>,
+
+        /// Tmux target (session, session:window.pane, or %pane)
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Wait for a line matching this regex
+        #[arg(long, value_name = "REGEX", conflicts_with = "quiet")]
+        pattern: Option<String>,
+
+        /// Wait for the pattern to disappear instead
+        #[arg(long, requires = "pattern")]
+        gone: bool,
+
+        /// Wait for the output to stop changing for this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        quiet: Option<u64>,
+
+        /// Give up after this many seconds (exits 124)
+        #[arg(long, default_value = "60")]
+        timeout: u64,
+
+        /// Lines to show around the matching line
+        #[arg(long, default_value = "3")]
+        context: usize,
+
+        /// Also match output that was already there when the wait started
+        #[arg(long)]
+        existing: bool,
+    },
+
+    /// Run a scripted dialog of sends and expects in the main pane or a task
+    Expect {
+        /// Optional task ID (e.g., t1); omit to use the main pane
+        task: Option<String>,
+
+        /// Tmux target (session, session:window.pane, or %pane)
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Read steps from this file, one per line
+        #[arg(long, value_name = "FILE", conflicts_with = "step")]
+        script: Option<std::path::PathBuf>,
+
+        /// A step: expect:/REGEX/, send:TEXT, timeout:SECONDS or capture
+        #[arg(long, value_parser = parse_expect_step)]
+        step: Vec<ExpectStep>,
+
+        /// Seconds each expect step waits until a timeout step changes it
+        #[arg(long, default_value = "30")]
+        timeout: u64,
     },
 
     /// Close a background task's pane
@@ -142,6 +293,20 @@ enum Commands {
         /// Tmux target (session, session:window.pane, or %pane)
         #[arg(short, long)]
         target: Option<String>,
+
+        /// Release a detached run even if its command still seems to be running
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the full output a truncated `tb run` saved
+    Output {
+        /// The run ID from the truncation banner
+        run_id: String,
+
+        /// Only print lines START:END (1-based; either end may be left open)
+        #[arg(long, value_parser = parse_line_range)]
+        range: Option<LineRange>,
     },
 }
 
@@ -152,6 +317,26 @@ fn main() {
 
     let cli = Cli::parse();
 
+    // clap declares conflicts between arguments, not with one value of an
+    // argument, so raise this one as the same kind of usage error.
+    if let Commands::Run {
+        stream: true,
+        ansi: AnsiMode::Html,
+        ..
+    } = cli.command
+    {
+        let mut command = Cli::command();
+        command.build();
+        command
+            .find_subcommand_mut("run")
+            .expect("run is a subcommand")
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the argument '--ansi html' cannot be used with '--stream'",
+            )
+            .exit();
+    }
+
     let result = match cli.command {
         Commands::Start { session } => cmd_start(session),
         Commands::Run {
@@ -162,6 +347,24 @@ fn main() {
             max_time,
             first,
             last,
+            stream,
+            capture,
+            split_stderr,
+            stdin,
+            display,
+            inject,
+            no_wait,
+            force,
+            kill_sequence,
+            on_timeout,
+            json,
+            ansi,
+            isolate,
+            cwd,
+            env,
+            profile,
+            quit_pager,
+            pipefail,
             command,
         } => cmd_run(RunOptions {
             target,
@@ -171,17 +374,73 @@ fn main() {
             max_time,
             first,
             last,
+            stream,
+            capture,
+            split_stderr,
+            stdin,
+            display,
+            inject,
+            no_wait,
+            force,
+            kill_sequence,
+            on_timeout,
+            json,
+            ansi,
+            isolate,
+            cwd,
+            env,
+            profile,
+            quit_pager,
+            pipefail,
             command,
         }),
         Commands::Info { target } => cmd_info(target),
-        Commands::Launch { target, command } => cmd_launch(target, command),
+        Commands::Launch {
+            target,
+            capture,
+            no_wait,
+            command,
+        } => cmd_launch(target, capture, no_wait, command),
         Commands::Check {
             task,
             target,
             first,
             last,
-        } => cmd_check(task, target, first, last),
-        Commands::Done { task, target } => cmd_done(task, target),
+            capture,
+            ansi,
+        } => cmd_check(task, target, (first, last), capture, ansi),
+        Commands::Wait {
+            task,
+            target,
+            pattern,
+            gone,
+            quiet,
+            timeout,
+            context,
+            existing,
+        } => cmd_wait(WaitOptions {
+            task,
+            target,
+            pattern,
+            gone,
+            quiet,
+            timeout,
+            context,
+            existing,
+        }),
+        Commands::Expect {
+            task,
+            target,
+            script,
+            step,
+            timeout,
+        } => cmd_expect(task, target, script, step, timeout),
+        Commands::Done {
+            task,
+            target,
+            force,
+        } => cmd_done(task, target, force),
+        Commands::Output { run_id, range } => cmd_output(run_id, range),
     };
 
     if let Err(e) = result {
@@ -237,6 +496,79 @@ enum RunShell {
     Sh,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum CaptureMode {
+    /// Re-read the pane scrollback with capture-pane
+    Scrollback,
+    /// Record the pane's raw output with pipe-pane into a log file
+    Pipe,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum OnTimeout {
+    /// Interrupt the command with --kill-sequence
+    Kill,
+    /// Leave it running and track it as a task in its pane
+    Detach,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum DisplayMode {
+    /// Leave the typed wrapper and marker lines visible in the pane
+    Raw,
+    /// Replace the wrapper with `[agent] $ <command>` and keep markers off-screen
+    Clean,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum InjectMode {
+    /// Type the command with `send-keys`, as if the human typed it
+    Keys,
+    /// Paste it from a tmux buffer as one bracketed paste, which line
+    /// editors insert as-is
+    Paste,
+    /// Paste long or multi-line commands, and type the rest
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum AnsiMode {
+    /// Plain text, with every escape sequence and control character removed
+    Strip,
+    /// Keep SGR color and style sequences (captures with `capture-pane -e`)
+    Keep,
+    /// Render colors as HTML spans inside a <pre> block
+    Html,
+}
+
+impl AnsiMode {
+    /// Whether output has to be read with its SGR sequences intact.
+    fn styled(self) -> bool {
+        self != Self::Strip
+    }
+
+    /// Turn captured output into what this mode prints. Lines map one to
+    /// one, so truncation works the same on the result.
+    fn render(self, text: &str) -> String {
+        match self {
+            Self::Strip => strip_ansi(text),
+            Self::Keep => text.to_string(),
+            Self::Html => ansi_to_html(text),
+        }
+    }
+
+    /// Print `text` as rendered by `print`, inside a <pre> block for HTML.
+    fn wrap(self, print: impl FnOnce()) {
+        if self == Self::Html {
+            println!("<pre class=\"tb-output\">");
+        }
+        print();
+        if self == Self::Html {
+            println!("</pre>");
+        }
+    }
+}
+
 impl From<RunShell> for ShellKind {
     fn from(value: RunShell) -> Self {
         match value {
@@ -270,6 +602,24 @@ struct RunOptions {
     max_time: u64,
     first: usize,
     last: usize,
+    stream: bool,
+    capture: CaptureMode,
+    split_stderr: bool,
+    stdin: bool,
+    display: DisplayMode,
+    inject: InjectMode,
+    no_wait: bool,
+    force: bool,
+    kill_sequence: KillSequence,
+    on_timeout: OnTimeout,
+    json: bool,
+    ansi: AnsiMode,
+    isolate: bool,
+    cwd: Option<String>,
+    env: Vec<(String, String)>,
+    profile: Option<String>,
+    quit_pager: bool,
+    pipefail: bool,
     command: Vec<String>,
 }
 
@@ -410,6 +760,23 @@ fn generate_session_id() -> Result<String, String> {
 }
 
 fn cmd_run(options: RunOptions) -> Result<(), String> {
+    let json = options.json;
+    let options_target = options.target.clone();
+    let shell = options.shell.map(Into::into).unwrap_or(ShellKind::Unknown);
+    match run_command(options) {
+        // Agents parsing JSON get errors in the same place as results.
+        Err(e) if json => {
+            let pane_id = resolve_tmux_target(options_target)
+                .and_then(|tmux_target| split_target(&tmux_target))
+                .ok();
+            println!("{}", run_error_json(&e, pane_id.as_deref(), shell.label()));
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+fn run_command(options: RunOptions) -> Result<(), String> {
     let RunOptions {
         target,
         shell,
@@ -418,19 +785,93 @@ fn cmd_run(options: RunOptions) -> Result<(), String> {
         max_time,
         first,
         last,
+        stream,
+        capture,
+        split_stderr,
+        stdin,
+        display,
+        inject,
+        no_wait,
+        force,
+        kill_sequence,
+        on_timeout,
+        json,
+        ansi,
+        isolate,
+        cwd,
+        env,
+        profile,
+        quit_pager,
+        pipefail,
         command,
     } = options;
 
     let shell_kind = shell.map(Into::into).unwrap_or(ShellKind::Unknown);
+    if pipefail && matches!(shell_kind, ShellKind::Sh | ShellKind::Unknown) {
+        return Err("--pipefail needs bash or fish, which report each pipeline stage's status.\n\nRerun with --shell bash or --shell fish.".to_string());
+    }
+    let stdin = if stdin {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        Some(bytes)
+    } else {
+        None
+    };
+    // Profile variables come first, so --env can still override them.
+    let env = match profile {
+        Some(name) => {
+            let mut profile_env = load_env_profile(&name)?;
+            profile_env.retain(|(key, _)| !env.iter().any(|(set, _)| set == key));
+            profile_env.extend(env);
+            profile_env
+        }
+        None => env,
+    };
+    let mut wrap = WrapOptions {
+        split_stderr,
+        stdin,
+        isolate: isolate || cwd.is_some() || !env.is_empty(),
+        cwd,
+        env,
+        ..WrapOptions::default()
+    };
+    let clean = display == DisplayMode::Clean;
+    // Clean markers never reach the screen, so only pipe-pane can see them.
+    let capture = if clean { CaptureMode::Pipe } else { capture };
 
     if dry_run {
-        println!("{}", build_shell_command(&command, "dryrunid", shell_kind));
+        // Without a pane to measure, assume an empty 80-column prompt line.
+        let shell_command = if clean {
+            build_clean_shell_command(&command, "dryrunid", shell_kind, &mut wrap, (0, 80))
+        } else {
+            build_shell_command(&command, "dryrunid", shell_kind, &wrap)
+        };
+        if json {
+            println!(
+                "{}",
+                json_object(&[("command", json_string(&shell_command))])
+            );
+        } else {
+            println!("{}", shell_command);
+        }
         return Ok(());
     }
 
     let check_target = target.clone();
     let tmux_target = resolve_tmux_target(target)?;
     let check_target = check_target.unwrap_or_else(|| tmux_target.clone());
+    let prompt_patterns = load_prompt_patterns()?;
+
+    // Queue behind any other tb command typing into this pane. The lock is
+    // held until tb exits.
+    let lock_wait = (!no_wait).then(|| std::time::Duration::from_secs(max_time));
+    let _pane_lock = acquire_lock(&pane_lock_name(&tmux_target)?, lock_wait, "pane")?;
+
+    leave_pane_mode(&tmux_target)?;
+    if !force {
+        ensure_idle_shell(&tmux_target)?;
+    }
 
     // Generate unique marker ID
     let marker_id: String = {
@@ -443,15 +884,177 @@ fn cmd_run(options: RunOptions) -> Result<(), String> {
             .collect()
     };
 
-    let start_marker = format!("___START_{}___", marker_id);
-    let end_marker_prefix = format!("___END_{}_", marker_id);
+    let source = match capture {
+        CaptureMode::Scrollback => OutputSource::Scrollback,
+        CaptureMode::Pipe => {
+            OutputSource::PipeLog(start_pipe_log(&tmux_target, &marker_id, false)?)
+        }
+    };
 
-    // Build the shell command to inject
-    let shell_command = build_shell_command(&command, &marker_id, shell_kind);
+    // Set aside anything the human has typed but not run; it is typed back
+    // once the agent's command is done.
+    let pending_input = if force {
+        Ok(None)
+    } else {
+        stash_pending_input(&tmux_target)
+    };
+    let pending_input = match pending_input {
+        Ok(pending_input) => pending_input,
+        Err(e) => {
+            if let OutputSource::PipeLog(log_path) = &source {
+                stop_pipe_log(&tmux_target, log_path);
+            }
+            return Err(e);
+        }
+    };
+
+    // Build the shell command to inject. Clean display measures the prompt,
+    // so this comes after any pending input is cut.
+    let shell_command = if clean {
+        pane_cursor_and_width(&tmux_target).map(|prompt| {
+            build_clean_shell_command(&command, &marker_id, shell_kind, &mut wrap, prompt)
+        })
+    } else {
+        Ok(build_shell_command(&command, &marker_id, shell_kind, &wrap))
+    };
+
+    // Remember who owns the terminal while the shell is idle, so a timeout
+    // can tell when the command has given it back.
+    let idle_foreground = pane_foreground(&tmux_target).ok();
+
+    let watch = RunWatch {
+        tmux_target: &tmux_target,
+        check_target: &check_target,
+        marker_id: &marker_id,
+        start_marker: format!("___START_{}___", marker_id),
+        end_marker_prefix: format!("___END_{}_", marker_id),
+        stderr_marker: format!("___STDERR_{}___", marker_id),
+        source: &source,
+        timeouts: (timeout, max_time),
+        output_window: (first, last),
+        stream,
+        idle_foreground,
+        kill_sequence: &kill_sequence,
+        on_timeout,
+        ansi,
+        prompt_patterns: &prompt_patterns,
+        quit_pager,
+    };
 
     // Send the command to tmux
+    let result = create_side_files(&marker_id, &wrap)
+        .and(shell_command)
+        .and_then(|shell_command| inject_command(&tmux_target, &shell_command, inject, &marker_id))
+        .and_then(|()| poll_for_completion(&watch));
+
+    // A detached run keeps recording into its log, and its wrapper still has
+    // stderr to replay, until `tb done`.
+    let detached = pane_option(&tmux_target, "@tb_marker").as_deref() == Some(marker_id.as_str());
+    if !detached {
+        remove_side_files(&marker_id);
+        if let OutputSource::PipeLog(log_path) = &source {
+            stop_pipe_log(&tmux_target, log_path);
+        }
+    }
+
+    if let Some(pending_input) = pending_input {
+        if detached {
+            // The command still has the human's pane, so keep the input on it
+            // for `tb check` or `tb done` to type back once it is over.
+            let _ = Command::new("tmux")
+                .args(["set-option", "-p", "-t", &tmux_target, "@tb_pending"])
+                .arg(&pending_input)
+                .status();
+        } else if !restore_pending_input(&tmux_target, &pending_input) {
+            eprintln!(
+                "Could not restore the user's half-typed command line: {}",
+                pending_input
+            );
+        }
+    }
+
+    let mut report = result?;
+    report.actions = HumanActions::find(&report.output);
+    let saved_run = save_run_output(&marker_id, &report.output, first, last);
+    report.output = ansi.render(&report.output);
+    report.stderr = report.stderr.map(|stderr| ansi.render(&stderr));
+    if json {
+        let pane_id = split_target(&tmux_target)?;
+        println!(
+            "{}",
+            report.to_json(&watch, saved_run, &pane_id, shell_kind.label())
+        );
+    } else {
+        report.print_text(&watch, saved_run);
+    }
+    let exit_code = report.tb_exit_code(pipefail);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+/// Commands longer than this are pasted rather than typed under
+/// `--inject auto`.
+const PASTE_THRESHOLD: usize = 1024;
+
+/// Type or paste the wrapped command into the pane and press Enter.
+///
+/// `send-keys` takes the whole wrapper as one argument, which large scripts
+/// outgrow, and line editors act on what it types: fish expands
+/// abbreviations, zsh autocorrects, and a literal tab triggers completion.
+/// A bracketed paste (`paste-buffer -p`) reaches the shell as one piece of
+/// text instead, newlines included.
+fn inject_command(
+    tmux_target: &str,
+    shell_command: &str,
+    inject: InjectMode,
+    marker_id: &str,
+) -> Result<(), String> {
+    let paste = match inject {
+        InjectMode::Keys => false,
+        InjectMode::Paste => true,
+        InjectMode::Auto => {
+            shell_command.len() > PASTE_THRESHOLD || shell_command.contains(['\n', '\t'])
+        }
+    };
+    if !paste {
+        return send_command_keys(tmux_target, shell_command);
+    }
+
+    let buffer = format!("tb-{}", marker_id);
+    let mut load = Command::new("tmux")
+        .args(["load-buffer", "-b", &buffer, "-"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to load tmux buffer: {}", e))?;
+    if let Some(mut stdin) = load.stdin.take() {
+        std::io::Write::write_all(&mut stdin, shell_command.as_bytes())
+            .map_err(|e| format!("Failed to load tmux buffer: {}", e))?;
+    }
+    let loaded = load
+        .wait()
+        .map_err(|e| format!("Failed to load tmux buffer: {}", e))?;
+    if !loaded.success() {
+        return Err("Failed to load tmux buffer.".to_string());
+    }
+
+    // -p brackets the paste when the shell has asked for bracketed paste;
+    // -d deletes the buffer once pasted.
+    let status = Command::new("tmux")
+        .args(["paste-buffer", "-p", "-d", "-b", &buffer, "-t", tmux_target])
+        .status()
+        .map_err(|e| format!("Failed to paste command into tmux: {}", e))?;
+    if !status.success() {
+        return Err("Failed to paste command into tmux.".to_string());
+    }
+
+    send_keys(tmux_target, &["Enter"])
+}
+
+fn send_command_keys(tmux_target: &str, shell_command: &str) -> Result<(), String> {
     let status = Command::new("tmux")
-        .args(["send-keys", "-t", &tmux_target, &shell_command, "Enter"])
+        .args(["send-keys", "-t", tmux_target, shell_command, "Enter"])
         .status()
         .map_err(|e| format!("Failed to send command to tmux: {}", e))?;
 
@@ -459,113 +1062,1183 @@ fn cmd_run(options: RunOptions) -> Result<(), String> {
         return Err("Failed to send command to tmux.".to_string());
     }
 
-    poll_for_completion(
-        &tmux_target,
-        &check_target,
-        &start_marker,
-        &end_marker_prefix,
-        (timeout, max_time),
-        (first, last),
-    )
+    Ok(())
 }
 
-fn poll_for_completion(
-    tmux_target: &str,
-    check_target: &str,
-    start_marker: &str,
-    end_marker_prefix: &str,
-    timeouts: (u64, u64),
-    output_window: (usize, usize),
-) -> Result<(), String> {
-    let (timeout, max_time) = timeouts;
-    let (first, last) = output_window;
-    let start_time = std::time::Instant::now();
-    let mut last_output_time = start_time;
-    let mut last_output_len = 0;
-    let poll_interval = std::time::Duration::from_millis(100);
-
-    loop {
-        std::thread::sleep(poll_interval);
+/// How long `tb info` and `tb launch` queue for a lock. Both hold theirs
+/// only briefly, unlike `tb run`, which waits up to its `--max-time`.
+const LOCK_WAIT: std::time::Duration = std::time::Duration::from_secs(30);
 
-        if start_time.elapsed().as_secs() >= max_time {
-            kill_running_command(tmux_target);
-            eprintln!("Timeout: max-time of {} seconds exceeded.", max_time);
-            std::process::exit(124);
+/// The directory lock files go in, which only the current user can open:
+/// `$XDG_RUNTIME_DIR/tb`, or `tb-locks-<uid>` in the temp directory. Other
+/// systems already give each user their own temp directory.
+fn lock_dir() -> Result<std::path::PathBuf, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+            .map(std::path::PathBuf::from)
+            .filter(|dir| dir.is_absolute());
+        // Anyone can create the name first in a shared temp directory.
+        let (lock_dir, shared) = match runtime_dir {
+            Some(dir) => (dir.join("tb"), false),
+            None => (
+                env::temp_dir().join(format!("tb-locks-{}", current_uid())),
+                true,
+            ),
+        };
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&lock_dir)
+            .map_err(|e| format!("Failed to create {}: {}", lock_dir.display(), e))?;
+
+        if shared {
+            let metadata = std::fs::symlink_metadata(&lock_dir)
+                .map_err(|e| format!("Failed to inspect {}: {}", lock_dir.display(), e))?;
+            if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.mode() & 0o077 != 0
+            {
+                return Err(format!(
+                    "Lock directory {} is not private to this user.\n\nRemove it, or set XDG_RUNTIME_DIR.",
+                    lock_dir.display()
+                ));
+            }
         }
+        Ok(lock_dir)
+    }
 
-        let output = capture_pane_scrollback(tmux_target)?;
-        let pane_content = String::from_utf8_lossy(&output.stdout);
+    #[cfg(not(unix))]
+    {
+        let lock_dir = env::temp_dir().join("tb-locks");
+        std::fs::create_dir_all(&lock_dir)
+            .map_err(|e| format!("Failed to create {}: {}", lock_dir.display(), e))?;
+        Ok(lock_dir)
+    }
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
 
-        if let Some(exit_code) = find_exit_code(&pane_content, end_marker_prefix) {
-            let cmd_output = extract_output(&pane_content, start_marker, end_marker_prefix);
-            print_output(&cmd_output, first, last);
+/// Take a cross-process lock named `name`, waiting up to `wait` for another
+/// tb process to release it (or failing at once when `wait` is `None`).
+///
+/// The lock is an advisory file lock, so it is released when the returned
+/// file is dropped or tb exits, including when tb is killed. Waiters poll
+/// for it, so they are not guaranteed to get it in the order they came.
+fn acquire_lock(
+    name: &str,
+    wait: Option<std::time::Duration>,
+    scope: &str,
+) -> Result<std::fs::File, String> {
+    let lock_path = lock_dir()?.join(format!("{}.lock", name));
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format!("Failed to open lock {}: {}", lock_path.display(), e))?;
 
-            if exit_code != 0 {
-                std::process::exit(exit_code);
+    let start_time = std::time::Instant::now();
+    let mut announced = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(format!("Failed to lock {}: {}", lock_path.display(), e));
             }
-            return Ok(());
         }
 
-        if pane_content.len() != last_output_len {
-            last_output_len = pane_content.len();
-            last_output_time = std::time::Instant::now();
+        let Some(wait) = wait else {
+            return Err(format!(
+                "Target {} is busy with another tb command.\n\nRetry later, or drop --no-wait to queue behind it.",
+                scope
+            ));
+        };
+        if start_time.elapsed() >= wait {
+            return Err(format!(
+                "Target {} is still busy with another tb command after {} seconds.",
+                scope,
+                wait.as_secs()
+            ));
         }
-
-        if last_output_time.elapsed().as_secs() >= timeout {
-            kill_running_command(tmux_target);
-            eprintln!("Timeout: no output for {} seconds.", timeout);
-            eprintln!("Check the pane first: tb check -t {}", check_target);
+        if !announced {
             eprintln!(
-                "This may be a syntax error before markers completed, or a slow/silent command."
+                "Waiting for another tb command in this {} to finish...",
+                scope
             );
-            eprintln!("Fix the command, or increase --timeout / emit progress output.");
-            std::process::exit(124);
+            announced = true;
         }
+        std::thread::sleep(std::time::Duration::from_millis(100));
     }
 }
 
-fn cmd_info(target: Option<String>) -> Result<(), String> {
-    let tmux_target = resolve_tmux_target(target)?;
-    let assessment = probe_shell_assessment(&tmux_target)?;
-    println!("{}", assessment.describe());
-    Ok(())
+/// Lock name for one pane, unique across tmux servers.
+fn pane_lock_name(tmux_target: &str) -> Result<String, String> {
+    tmux_lock_name(tmux_target, "pane-#{pid}-#{pane_id}")
 }
 
-fn is_special_tmux_target(target: &str) -> bool {
-    target.starts_with('%') || target.contains(':') || target.contains('.')
+/// Lock name for the window whose panes share one task-ID namespace.
+fn scope_lock_name(tmux_target: &str) -> Result<String, String> {
+    tmux_lock_name(tmux_target, "window-#{pid}-#{session_id}-#{window_id}")
 }
 
-fn tmux_session_exists_literal(target: &str) -> bool {
+fn tmux_lock_name(tmux_target: &str, format: &str) -> Result<String, String> {
     let output = Command::new("tmux")
-        .args(["list-sessions", "-F", "#{session_name}"])
-        .output();
+        .args(["display-message", "-p", "-t", tmux_target, format])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
 
-    match output {
-        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
-            .lines()
-            .any(|session_name| session_name == target),
-        _ => false,
+    if !output.status.success() {
+        return Err("Failed to inspect tmux target.".to_string());
     }
-}
 
-fn tmux_pane_target_exists(target: &str) -> bool {
-    Command::new("tmux")
-        .args(["display-message", "-p", "-t", target, "#{pane_id}"])
-        .status()
-        .map(|s| s.success())
-        .unwrap_or(false)
+    // Drop tmux's `%`, `$` and `@` ID sigils so the name is a plain filename.
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect())
 }
 
-fn resolve_tmux_target(target: Option<String>) -> Result<String, String> {
-    let target = target.ok_or_else(|| {
-        "No target specified.\n\nUse --target TARGET.\nAsk the user which tmux target to use."
-            .to_string()
-    })?;
-
-    if is_special_tmux_target(&target) {
-        if tmux_pane_target_exists(&target) {
-            return Ok(target);
-        }
+/// Make sure keys sent to the pane reach its program, not a tmux pane mode.
+///
+/// Copy mode and view mode only hold the human's scroll position, so leave
+/// them and say so. Any other mode (clock, tree, buffer, ...) is the human's
+/// to close.
+fn leave_pane_mode(tmux_target: &str) -> Result<(), String> {
+    let Some(mode) = pane_mode(tmux_target)? else {
+        return Ok(());
+    };
+
+    if !matches!(mode.as_str(), "copy-mode" | "view-mode") {
+        return Err(format!(
+            "Target pane is in tmux {}, so keys would go to tmux instead of the shell.\n\nAsk the user to leave it (usually q or Escape), then retry.",
+            mode
+        ));
+    }
+
+    let _ = Command::new("tmux")
+        .args(["send-keys", "-X", "-t", tmux_target, "cancel"])
+        .status();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(1);
+    while pane_mode(tmux_target)?.is_some() {
+        if std::time::Instant::now() >= deadline {
+            return Err(format!(
+                "Target pane is stuck in tmux {}.\n\nAsk the user to leave it (usually q or Escape), then retry.",
+                mode
+            ));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    eprintln!(
+        "Note: left tmux {} in the target pane so keys reach the shell.",
+        mode
+    );
+    Ok(())
+}
+
+/// The tmux mode the pane is in, such as `copy-mode`, if any.
+fn pane_mode(tmux_target: &str) -> Result<Option<String>, String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{pane_in_mode} #{pane_mode}",
+        ])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
+
+    if !output.status.success() {
+        return Err("Failed to inspect tmux target.".to_string());
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(match text.trim().split_once(' ') {
+        Some(("1", mode)) => Some(mode.to_string()),
+        _ => None,
+    })
+}
+
+/// Shells that are safe to type a command line into while they sit at their
+/// prompt.
+const IDLE_PROGRAMS: &[&str] = &[
+    "bash", "sh", "dash", "ash", "zsh", "fish", "ksh", "mksh", "tcsh", "csh", "nu",
+];
+
+/// Refuse to type into a pane whose foreground program is not a shell.
+fn ensure_idle_shell(tmux_target: &str) -> Result<(), String> {
+    let foreground = pane_foreground(tmux_target)?;
+    if foreground.is_idle_shell() {
+        return Ok(());
+    }
+
+    let name = foreground.program.trim_start_matches('-');
+
+    Err(format!(
+        "Target pane is running {}; ask the user to return to a shell.\n\nIf the keystrokes really should go to {}, rerun with --force.",
+        name, name
+    ))
+}
+
+/// What owns the pane's terminal right now.
+#[derive(Debug, Eq, PartialEq)]
+struct Foreground {
+    /// The pane's own process, normally the human's login shell.
+    pane_pid: Option<i32>,
+    /// The foreground process group, where `/proc` can tell.
+    pgid: Option<i32>,
+    program: String,
+}
+
+impl Foreground {
+    /// A shell owns the terminal and is sitting at its prompt: the pane's own
+    /// shell, or one the human started inside it. A shell name alone is not
+    /// enough, since a script run by `bash` or `sh` has its own process group
+    /// too and may be reading a line of its own.
+    fn is_idle_shell(&self) -> bool {
+        let name = self.program.trim_start_matches('-');
+        IDLE_PROGRAMS.contains(&name)
+            && match (self.pgid, self.pane_pid) {
+                (Some(pgid), Some(pane_pid)) => pgid == pane_pid || reads_from_terminal(pgid),
+                _ => true,
+            }
+    }
+}
+
+/// Whether process `pid` is a shell started to read commands from the
+/// terminal, rather than to run a script or a `-c` string.
+fn reads_from_terminal(pid: i32) -> bool {
+    let Ok(cmdline) = std::fs::read(format!("/proc/{}/cmdline", pid)) else {
+        return false;
+    };
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .skip(1);
+    while let Some(arg) = args.next() {
+        match arg {
+            b"--rcfile" | b"--init-file" | b"-o" | b"+o" | b"-O" | b"+O" => {
+                args.next();
+            }
+            _ if arg.starts_with(b"--") => {}
+            _ if arg.starts_with(b"-") || arg.starts_with(b"+") => {
+                if arg.contains(&b'c') {
+                    return false;
+                }
+            }
+            // A script to run.
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Find the program in the pane's foreground.
+///
+/// tmux's `pane_current_command` can lag behind or name the shell while a
+/// job it started owns the terminal, so on Linux read the foreground process
+/// group straight from the pane process's `/proc/<pid>/stat` (field 8,
+/// `tpgid`) and name its newest process. Elsewhere, trust tmux.
+fn pane_foreground(tmux_target: &str) -> Result<Foreground, String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{pane_pid} #{pane_current_command}",
+        ])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
+
+    if !output.status.success() {
+        return Err("Failed to inspect tmux target.".to_string());
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let (pane_pid, current_command) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+
+    let pane_pid = pane_pid.parse::<i32>().ok();
+    let pgid = pane_pid
+        .and_then(|pane_pid| std::fs::read_to_string(format!("/proc/{}/stat", pane_pid)).ok())
+        .and_then(|stat| {
+            // The command name is parenthesised and may contain spaces.
+            let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+            fields.get(5)?.parse::<i32>().ok()
+        })
+        .filter(|&tpgid| tpgid > 0);
+
+    Ok(Foreground {
+        pane_pid,
+        pgid,
+        program: pgid
+            .and_then(|pgid| processes_in_group(pgid).pop())
+            .unwrap_or_else(|| current_command.to_string()),
+    })
+}
+
+/// Name the processes in process group `pgid`, oldest first.
+///
+/// An idle shell is alone in its group. While a job runs, the newest process
+/// is what the job is busy with: `sleep` rather than the `sh` running the
+/// script around it, or the group leader that has already exited. A script
+/// and the command it starts often share a clock tick of start time, so ties
+/// go to the higher pid, which was forked later.
+fn processes_in_group(pgid: i32) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut processes: Vec<(u64, i32, String)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let pid = entry.file_name().to_str()?.parse::<i32>().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            let (head, tail) = stat.rsplit_once(')')?;
+            let name = head.split_once('(')?.1;
+            // Fields after the name start at field 3 (state); pgrp is field
+            // 5 and starttime field 22.
+            let fields: Vec<&str> = tail.split_whitespace().collect();
+            let group = fields.get(2)?.parse::<i32>().ok()?;
+            let start_time = fields.get(19)?.parse::<u64>().ok()?;
+            (group == pgid).then(|| (start_time, pid, name.to_string()))
+        })
+        .collect();
+    processes.sort();
+    processes.into_iter().map(|(_, _, name)| name).collect()
+}
+
+/// Characters that typically end a shell prompt.
+const PROMPT_ENDINGS: &[char] = &['$', '#', '>', '%', '❯', '»', 'λ'];
+
+/// The command line under the pane's cursor.
+struct PromptLine {
+    /// The whole logical line, wrapped rows joined, trailing blanks trimmed.
+    text: String,
+    /// The cursor's own row, trailing blanks trimmed.
+    row: String,
+    cursor_x: usize,
+}
+
+impl PromptLine {
+    /// The cursor sits one space past the last visible character, where a
+    /// prompt leaves it.
+    fn cursor_after_text(&self) -> bool {
+        self.cursor_x == self.row.chars().count() + 1
+    }
+
+    /// What is left of the cursor ends in a prompt character, as with a
+    /// prompt that has no trailing space or a right prompt past the cursor.
+    fn cursor_after_prompt(&self) -> bool {
+        let left: String = self.row.chars().take(self.cursor_x).collect();
+        left.trim_end().ends_with(PROMPT_ENDINGS)
+    }
+
+    fn is_bare_prompt(&self) -> bool {
+        self.cursor_after_text() && self.text.ends_with(PROMPT_ENDINGS)
+    }
+}
+
+fn read_prompt_line(tmux_target: &str) -> Result<PromptLine, String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{cursor_x} #{cursor_y}",
+        ])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let (cursor_x, cursor_y) = text.trim().split_once(' ').unwrap_or(("0", "0"));
+    let cursor_x = cursor_x.parse::<usize>().unwrap_or(0);
+
+    let capture = |extra: &[&str]| -> Result<String, String> {
+        let output = Command::new("tmux")
+            .args(["capture-pane", "-p", "-t", tmux_target, "-E", cursor_y])
+            .args(extra)
+            .output()
+            .map_err(|e| format!("Failed to capture pane: {}", e))?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        Ok(text.lines().last().unwrap_or("").trim_end().to_string())
+    };
+
+    Ok(PromptLine {
+        text: capture(&["-J"])?,
+        row: capture(&["-S", cursor_y])?,
+        cursor_x,
+    })
+}
+
+/// Cut anything the human has typed at the prompt but not run yet, and
+/// return it so it can be typed back after the agent's command.
+///
+/// The line editor does the cutting (end of line, then kill back to the
+/// prompt). Whatever disappeared from the cursor line is the pending input.
+/// If the result does not look like a bare prompt plus that text, put it
+/// back and refuse rather than guess.
+fn stash_pending_input(tmux_target: &str) -> Result<Option<String>, String> {
+    // Right after a previous command the shell may not have drawn its
+    // prompt yet; give it a moment rather than mistake the blank line.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(1);
+    let mut before = read_prompt_line(tmux_target)?;
+    while before.text.is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        before = read_prompt_line(tmux_target)?;
+    }
+    // A cursor line that is still blank holds nothing typed: the prompt is
+    // just slow to draw.
+    if before.text.is_empty() || before.is_bare_prompt() {
+        return Ok(None);
+    }
+
+    let refusal = "Target pane has a half-typed command line that tb could not set aside.\n\nAsk the user to run or clear what they have typed, then retry.";
+    send_keys(tmux_target, &["C-e", "C-u"])?;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(1);
+    let mut after = read_prompt_line(tmux_target)?;
+    while after.text == before.text && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        after = read_prompt_line(tmux_target)?;
+    }
+
+    if after.text == before.text {
+        // Nothing to cut: an unfamiliar prompt with an empty command line.
+        // Text left of the cursor that is not a prompt is input the line
+        // editor would not cut.
+        return if before.cursor_after_text() || before.cursor_after_prompt() {
+            Ok(None)
+        } else {
+            Err(refusal.to_string())
+        };
+    }
+
+    match before.text.strip_prefix(&after.text) {
+        Some(pending) if after.cursor_after_text() => {
+            let pending = pending.trim_start();
+            Ok((!pending.is_empty()).then(|| pending.to_string()))
+        }
+        _ => {
+            let _ = send_keys(tmux_target, &["C-y"]);
+            Err(refusal.to_string())
+        }
+    }
+}
+
+/// Type the human's stashed input back at the prompt, if the shell is back.
+fn restore_pending_input(tmux_target: &str, pending_input: &str) -> bool {
+    ensure_idle_shell(tmux_target).is_ok()
+        && Command::new("tmux")
+            .args(["send-keys", "-t", tmux_target, "-l", "--", pending_input])
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+}
+
+/// Type back the input stashed when a run detached, once its command is
+/// over. It stays on the pane if the shell is not back yet.
+fn restore_detached_pending_input(pane_id: &str) -> Option<String> {
+    let output = Command::new("tmux")
+        .args(["show-options", "-p", "-v", "-t", pane_id, "@tb_pending"])
+        .output()
+        .ok()?;
+    let pending_input = String::from_utf8_lossy(&output.stdout);
+    let pending_input = pending_input.strip_suffix('\n').unwrap_or(&pending_input);
+    if !output.status.success() || pending_input.is_empty() {
+        return None;
+    }
+
+    if !restore_pending_input(pane_id, pending_input) {
+        return Some(pending_input.to_string());
+    }
+    let _ = Command::new("tmux")
+        .args(["set-option", "-p", "-u", "-t", pane_id, "@tb_pending"])
+        .status();
+    None
+}
+
+fn send_keys(tmux_target: &str, keys: &[&str]) -> Result<(), String> {
+    let status = Command::new("tmux")
+        .args(["send-keys", "-t", tmux_target])
+        .args(keys)
+        .status()
+        .map_err(|e| format!("Failed to send keys to tmux: {}", e))?;
+
+    if !status.success() {
+        return Err("Failed to send keys to tmux.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Everything `poll_for_completion` needs to follow one injected command.
+struct RunWatch<'a> {
+    tmux_target: &'a str,
+    check_target: &'a str,
+    marker_id: &'a str,
+    start_marker: String,
+    end_marker_prefix: String,
+    stderr_marker: String,
+    source: &'a OutputSource,
+    timeouts: (u64, u64),
+    output_window: (usize, usize),
+    stream: bool,
+    idle_foreground: Option<Foreground>,
+    kill_sequence: &'a KillSequence,
+    on_timeout: OnTimeout,
+    ansi: AnsiMode,
+    prompt_patterns: &'a [regex::Regex],
+    quit_pager: bool,
+}
+
+/// What became of one injected command, for `tb run` to print as text or
+/// as JSON.
+struct RunReport {
+    /// Output after the start marker: all of it once the command finished,
+    /// or whatever it had printed when a timeout fired.
+    output: String,
+    /// Stderr replayed after the command, with `--split-stderr`.
+    stderr: Option<String>,
+    /// The command's own exit code; `None` when a timeout fired first.
+    exit_code: Option<i32>,
+    /// Exit status of each stage of the command's last pipeline, when the
+    /// shell reports them (bash and fish).
+    pipestatus: Vec<i32>,
+    timeout: Option<TimeoutReport>,
+    blocked: Option<BlockedReport>,
+    /// Sign-in links and codes found in the output.
+    actions: HumanActions,
+    /// Lines `--stream` already printed while the command ran.
+    streamed_lines: usize,
+    duration: std::time::Duration,
+}
+
+struct TimeoutReport {
+    /// Which timeout fired: `no_output` or `max_time`.
+    kind: &'static str,
+    reason: String,
+    advice: Vec<String>,
+    /// Task ID the command kept running as, with `--on-timeout detach`.
+    task_id: Option<String>,
+    /// The kill sequence step that stopped the command, if any did.
+    stopped_by: Option<KillSignal>,
+    /// The visible screen from just before the interrupt.
+    screen: String,
+}
+
+/// What a command is waiting on the human for.
+enum Blocker {
+    /// A password or confirmation prompt, as the pane shows it.
+    Prompt(String),
+    /// A browser sign-in, whose link and code are in `RunReport::actions`.
+    SignIn,
+    /// A pager showing the output, such as `less`.
+    Pager(String),
+    /// A program on the alternate screen, such as an editor.
+    FullScreen(String),
+}
+
+impl Blocker {
+    fn kind(&self) -> &'static str {
+        match self {
+            Blocker::Prompt(_) => "prompt",
+            Blocker::SignIn => "sign_in",
+            Blocker::Pager(_) => "pager",
+            Blocker::FullScreen(_) => "full_screen",
+        }
+    }
+}
+
+/// A command left running because it waits for the human.
+struct BlockedReport {
+    blocker: Blocker,
+    /// Task ID the command keeps running as until the human is done.
+    task_id: Option<String>,
+    /// The visible screen when tb stopped waiting.
+    screen: String,
+}
+
+/// Exit code for a run that stopped because a human has to act in the pane.
+/// Reserved like 124 for timeouts, though a command may exit with it too;
+/// `--json` tells them apart with `needs_human`.
+const NEEDS_HUMAN_EXIT_CODE: i32 = 125;
+
+impl RunReport {
+    /// The exit code `tb run` finishes with: the command's own code, 124
+    /// when either timeout fires, or 125 when it waits for the human. With
+    /// `pipefail`, the last failed pipeline stage's status wins, as with the
+    /// shell option.
+    fn tb_exit_code(&self, pipefail: bool) -> i32 {
+        if self.blocked.is_some() {
+            return NEEDS_HUMAN_EXIT_CODE;
+        }
+        let failed_stage = self.pipestatus.iter().rev().find(|&&code| code != 0);
+        match (self.exit_code, failed_stage) {
+            (Some(_), Some(&stage_code)) if pipefail => stage_code,
+            (exit_code, _) => exit_code.unwrap_or(124),
+        }
+    }
+
+    /// Explain a signal death or a failed pipeline stage, which the exit
+    /// code alone hides.
+    fn status_notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if let Some(code) = self.exit_code
+            && let Some(signal) = signal_from_status(code)
+        {
+            notes.push(format!(
+                "Command terminated by SIG{} (exit code {}).",
+                signal, code
+            ));
+        }
+        if self.pipestatus.len() > 1 && self.pipestatus.iter().any(|&code| code != 0) {
+            let stages: Vec<String> = self
+                .pipestatus
+                .iter()
+                .map(|&code| match signal_from_status(code) {
+                    Some(signal) => format!("{} (SIG{})", code, signal),
+                    None => code.to_string(),
+                })
+                .collect();
+            notes.push(format!("Pipeline stage statuses: {}", stages.join(" | ")));
+        }
+        notes
+    }
+
+    /// Print the output on stdout and any explanation on stderr.
+    fn print_text(&self, watch: &RunWatch, saved_run: Option<&str>) {
+        let (first, last) = watch.output_window;
+        watch
+            .ansi
+            .wrap(|| print_partial_output(&self.output, self.streamed_lines, watch, saved_run));
+
+        self.actions.print();
+
+        if let Some(blocked) = &self.blocked {
+            match &blocked.blocker {
+                Blocker::Prompt(line) => {
+                    eprintln!("Command is waiting at a prompt: {}", line);
+                    eprintln!("tb does not answer it; ask the user to respond in the pane.");
+                }
+                Blocker::SignIn => eprintln!(
+                    "Command is waiting for the user to sign in; pass them the link and code."
+                ),
+                Blocker::Pager(program) => {
+                    eprintln!(
+                        "Command opened the pager {}, which waits for keys.",
+                        program
+                    );
+                    eprintln!(
+                        "Ask the user to quit it, or rerun with --isolate --profile noninteractive or --quit-pager."
+                    );
+                }
+                Blocker::FullScreen(program) => {
+                    eprintln!(
+                        "Command opened {}, which has taken over the screen.",
+                        program
+                    );
+                    eprintln!("Ask the user to finish in the pane, or run it non-interactively.");
+                }
+            }
+            if matches!(blocked.blocker, Blocker::Pager(_) | Blocker::FullScreen(_)) {
+                print_screen_snapshot("Screen now:", &blocked.screen);
+            }
+            if let Some(task_id) = &blocked.task_id {
+                println!();
+                println!("Command still running as task {}.", task_id);
+                println!(
+                    "Check status with: tb check --target {} {}",
+                    watch.check_target, task_id
+                );
+            }
+            return;
+        }
+
+        let Some(timeout) = &self.timeout else {
+            if let Some(stderr) = self.stderr.as_ref().filter(|text| !text.is_empty()) {
+                let stderr = truncate_output(stderr, first, last, None);
+                match watch.ansi {
+                    AnsiMode::Html => eprint!("<pre class=\"tb-output\">\n{}</pre>\n", stderr),
+                    _ => eprint!("{}", stderr),
+                }
+            }
+            for note in self.status_notes() {
+                eprintln!("{}", note);
+            }
+            return;
+        };
+
+        if let Some(task_id) = &timeout.task_id {
+            eprintln!("{}", timeout.reason);
+            println!();
+            println!("Command still running as task {}.", task_id);
+            println!(
+                "Check status with: tb check --target {} {}",
+                watch.check_target, task_id
+            );
+            return;
+        }
+
+        match timeout.stopped_by {
+            Some(signal) => eprintln!("Stopped the command with SIG{}.", signal.name()),
+            None => eprintln!(
+                "The command may still be running after SIG{}; ask the user to check the pane.",
+                watch
+                    .kill_sequence
+                    .0
+                    .last()
+                    .map(|step| step.signal.name())
+                    .unwrap_or("INT")
+            ),
+        }
+        eprintln!("{}", timeout.reason);
+        for line in &timeout.advice {
+            eprintln!("{}", line);
+        }
+        print_screen_snapshot("Screen before the interrupt:", &timeout.screen);
+    }
+
+    /// Render the report as one JSON object. `output` keeps the same
+    /// `--first`/`--last` lines as text mode, without the truncation banner.
+    fn to_json(
+        &self,
+        watch: &RunWatch,
+        saved_run: Option<&str>,
+        pane_id: &str,
+        shell: &str,
+    ) -> String {
+        let (first, last) = watch.output_window;
+        let lines: Vec<&str> = self.output.lines().collect();
+        let truncated_lines = lines.len().saturating_sub(first + last);
+        let output = if truncated_lines == 0 {
+            lines.join("\n")
+        } else {
+            [&lines[..first], &lines[lines.len() - last..]]
+                .concat()
+                .join("\n")
+        };
+        let timeout = self.timeout.as_ref();
+        let blocked = self.blocked.as_ref();
+        let pipestatus = match self.pipestatus.as_slice() {
+            [] => "null".to_string(),
+            stages => format!(
+                "[{}]",
+                stages
+                    .iter()
+                    .map(i32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        };
+        let signal = self
+            .exit_code
+            .and_then(signal_from_status)
+            .map(|signal| format!("SIG{}", signal));
+        let full_output = saved_run
+            .and_then(run_output_path)
+            .map(|path| path.display().to_string());
+        let fields = [
+            ("output", json_string(&output)),
+            ("stderr", json_option(self.stderr.as_deref())),
+            (
+                "exit_code",
+                self.exit_code
+                    .map_or_else(|| "null".to_string(), |code| code.to_string()),
+            ),
+            ("signal", json_option(signal.as_deref())),
+            ("pipestatus", pipestatus),
+            ("timed_out", timeout.is_some().to_string()),
+            ("timeout", json_option(timeout.map(|t| t.kind))),
+            (
+                "stopped_by",
+                json_option(timeout.and_then(|t| t.stopped_by).map(KillSignal::name)),
+            ),
+            ("needs_human", blocked.is_some().to_string()),
+            (
+                "waiting_for",
+                json_option(blocked.map(|b| b.blocker.kind())),
+            ),
+            (
+                "prompt",
+                json_option(blocked.and_then(|b| match &b.blocker {
+                    Blocker::Prompt(line) => Some(line.as_str()),
+                    _ => None,
+                })),
+            ),
+            (
+                "program",
+                json_option(blocked.and_then(|b| match &b.blocker {
+                    Blocker::Pager(program) | Blocker::FullScreen(program) => {
+                        Some(program.as_str())
+                    }
+                    _ => None,
+                })),
+            ),
+            ("action_needed", self.actions.to_json()),
+            (
+                "task",
+                json_option(
+                    timeout
+                        .and_then(|t| t.task_id.as_deref())
+                        .or_else(|| blocked.and_then(|b| b.task_id.as_deref())),
+                ),
+            ),
+            ("duration_ms", self.duration.as_millis().to_string()),
+            ("truncated_lines", truncated_lines.to_string()),
+            ("full_output", json_option(full_output.as_deref())),
+            ("pane_id", json_string(pane_id)),
+            ("shell", json_string(shell)),
+        ];
+        json_object(&fields)
+    }
+}
+
+/// The JSON for a run that failed before it had a report: the same fields as
+/// `RunReport::to_json`, null where nothing is known, plus the error.
+fn run_error_json(error: &str, pane_id: Option<&str>, shell: &str) -> String {
+    let mut fields: Vec<(&str, String)> = [
+        "output",
+        "stderr",
+        "exit_code",
+        "signal",
+        "pipestatus",
+        "timed_out",
+        "timeout",
+        "stopped_by",
+        "needs_human",
+        "waiting_for",
+        "prompt",
+        "program",
+        "action_needed",
+        "task",
+        "duration_ms",
+        "truncated_lines",
+        "full_output",
+    ]
+    .into_iter()
+    .map(|key| (key, "null".to_string()))
+    .collect();
+    fields.push(("pane_id", json_option(pane_id)));
+    fields.push(("shell", json_string(shell)));
+    fields.push(("error", json_string(error)));
+    json_object(&fields)
+}
+
+/// Sign-in links and one-time device codes a command printed for the human,
+/// as `aws sso login`, `gh auth login` and `az login` do.
+#[derive(Default)]
+struct HumanActions {
+    urls: Vec<String>,
+    codes: Vec<String>,
+}
+
+static URL_PATTERN: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r#"https?://[^\s"'<>]+"#).unwrap());
+
+/// Words around a link or code that mark it as a sign-in step rather than,
+/// say, a documentation link or an error code in build output.
+static AUTH_CONTEXT_PATTERN: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+    regex::Regex::new(
+        r"(?i)\b(?:log ?in|sign ?in|auth|authenticat\w*|authori[sz]\w*|oauth|device|verif\w*|activate|one-time code|browser)\b",
+    )
+    .unwrap()
+});
+
+/// A code after the word "code" (`enter the code ABCD12345`, gh's
+/// `one-time code: 1A2B-3C4D`), possibly on the next line, or a bare
+/// `ABCD-EFGH` style code anywhere.
+static DEVICE_CODE_PATTERN: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+    regex::Regex::new(
+        r"\b(?i:code)\b:?\s+([A-Z0-9]{4}-[A-Z0-9]{4}|[A-Z0-9]{6,12})\b|\b[A-Z0-9]{4}-[A-Z0-9]{4}\b",
+    )
+    .unwrap()
+});
+
+impl HumanActions {
+    fn find(output: &str) -> HumanActions {
+        let plain = strip_ansi(output);
+        let lines: Vec<&str> = plain.lines().collect();
+        let mut actions = HumanActions::default();
+
+        for (index, line) in lines.iter().enumerate() {
+            let context = &lines[index.saturating_sub(2)..=index];
+            for url in URL_PATTERN.find_iter(line) {
+                let url = url
+                    .as_str()
+                    .trim_end_matches(['.', ',', ';', ':', ')', ']']);
+                let is_auth = AUTH_CONTEXT_PATTERN.is_match(url)
+                    || context
+                        .iter()
+                        .any(|line| AUTH_CONTEXT_PATTERN.is_match(line));
+                if is_auth && !actions.urls.iter().any(|seen| seen == url) {
+                    actions.urls.push(url.to_string());
+                }
+            }
+        }
+
+        // Join each line to the next so "enter the code:" finds a code
+        // printed on a line of its own.
+        let joined = lines
+            .iter()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join(" ");
+        for found in DEVICE_CODE_PATTERN.captures_iter(&joined) {
+            // A bare `ABCD-1234` only counts next to a sign-in link. A
+            // labelled one also counts after sign-in wording, which
+            // "Error code ABCDEF1" in build output lacks.
+            let whole = found.get(0).unwrap();
+            let (code, after_word) = match found.get(1) {
+                Some(code) => (code.as_str(), true),
+                None => (whole.as_str(), false),
+            };
+            let mut context_start = whole.start().saturating_sub(200);
+            while !joined.is_char_boundary(context_start) {
+                context_start -= 1;
+            }
+            let near_auth = AUTH_CONTEXT_PATTERN.is_match(&joined[context_start..whole.end()]);
+            let is_code = (!actions.urls.is_empty() || (after_word && near_auth))
+                && code.chars().any(|c| c.is_ascii_uppercase());
+            if is_code && !actions.codes.iter().any(|seen| seen == code) {
+                actions.codes.push(code.to_string());
+            }
+        }
+
+        actions
+    }
+
+    fn is_empty(&self) -> bool {
+        self.urls.is_empty() && self.codes.is_empty()
+    }
+
+    /// Print the "Action needed by human" section, if there is anything to do.
+    fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+        println!();
+        println!("Action needed by human:");
+        for url in &self.urls {
+            println!("  Open {}", url);
+        }
+        for code in &self.codes {
+            println!("  Enter code {}", code);
+        }
+    }
+
+    fn to_json(&self) -> String {
+        if self.is_empty() {
+            return "null".to_string();
+        }
+        let list = |items: &[String]| {
+            let items: Vec<String> = items.iter().map(|item| json_string(item)).collect();
+            format!("[{}]", items.join(","))
+        };
+        json_object(&[("urls", list(&self.urls)), ("codes", list(&self.codes))])
+    }
+}
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Wait for the end marker, or deal with whichever timeout fires first.
+fn poll_for_completion(watch: &RunWatch) -> Result<RunReport, String> {
+    let RunWatch {
+        tmux_target,
+        check_target,
+        start_marker,
+        end_marker_prefix,
+        stderr_marker,
+        source,
+        timeouts: (timeout, max_time),
+        stream,
+        ..
+    } = watch;
+    let (timeout, max_time, stream) = (*timeout, *max_time, *stream);
+    let start_time = std::time::Instant::now();
+    let mut last_output_time = start_time;
+    let mut last_output_len = 0;
+    let mut streamed_lines = 0;
+    let poll_interval = std::time::Duration::from_millis(100);
+
+    loop {
+        std::thread::sleep(poll_interval);
+
+        if start_time.elapsed().as_secs() >= max_time {
+            let reason = format!("Timeout: max-time of {} seconds exceeded.", max_time);
+            return Ok(finish_timed_out_run(
+                watch,
+                streamed_lines,
+                start_time,
+                "max_time",
+                reason,
+                Vec::new(),
+            ));
+        }
+
+        let captured = source.read(tmux_target, stream, watch.ansi.styled())?;
+        let pane_content = captured.content.as_str();
+
+        if let Some((exit_code, pipestatus)) = find_end_status(pane_content, end_marker_prefix) {
+            let cmd_output = extract_output(pane_content, start_marker, end_marker_prefix);
+            let (output, stderr) = split_stderr_output(&cmd_output, stderr_marker);
+            return Ok(RunReport {
+                output,
+                stderr,
+                exit_code: Some(exit_code),
+                pipestatus,
+                timeout: None,
+                blocked: None,
+                actions: HumanActions::default(),
+                streamed_lines,
+                duration: start_time.elapsed(),
+            });
+        }
+
+        if stream {
+            streamed_lines = stream_new_lines(
+                pane_content,
+                captured.complete_lines,
+                start_marker,
+                stderr_marker,
+                streamed_lines,
+            );
+        }
+
+        if pane_content.len() != last_output_len {
+            last_output_len = pane_content.len();
+            last_output_time = std::time::Instant::now();
+        }
+
+        if last_output_time.elapsed() >= PROMPT_SETTLE_TIME {
+            let output = extract_partial_output(pane_content, start_marker);
+            let actions = HumanActions::find(&output);
+            let blocker = match find_waiting_prompt(&output, watch.prompt_patterns) {
+                Some(prompt) => Some(Blocker::Prompt(prompt)),
+                None if !actions.urls.is_empty() && !actions.codes.is_empty() => {
+                    Some(Blocker::SignIn)
+                }
+                None => find_screen_takeover(tmux_target),
+            };
+            match blocker {
+                Some(Blocker::Pager(_)) if watch.quit_pager => {
+                    send_keys(tmux_target, &["q"])?;
+                    last_output_time = std::time::Instant::now();
+                    continue;
+                }
+                Some(blocker) => {
+                    return Ok(finish_blocked_run(
+                        watch,
+                        streamed_lines,
+                        start_time,
+                        blocker,
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        if last_output_time.elapsed().as_secs() >= timeout {
+            let reason = format!("Timeout: no output for {} seconds.", timeout);
+            let advice = vec![
+                format!("Check the pane first: tb check -t {}", check_target),
+                "This may be a syntax error before markers completed, or a slow/silent command."
+                    .to_string(),
+                "Fix the command, or increase --timeout / emit progress output.".to_string(),
+            ];
+            return Ok(finish_timed_out_run(
+                watch,
+                streamed_lines,
+                start_time,
+                "no_output",
+                reason,
+                advice,
+            ));
+        }
+    }
+}
+
+fn cmd_info(target: Option<String>) -> Result<(), String> {
+    let tmux_target = resolve_tmux_target(target)?;
+    // The probe types into the pane too, so it queues like `tb run`.
+    let _pane_lock = acquire_lock(&pane_lock_name(&tmux_target)?, Some(LOCK_WAIT), "pane")?;
+    leave_pane_mode(&tmux_target)?;
+    let assessment = probe_shell_assessment(&tmux_target)?;
+    println!("{}", assessment.describe());
+    Ok(())
+}
+
+fn is_special_tmux_target(target: &str) -> bool {
+    target.starts_with('%') || target.contains(':') || target.contains('.')
+}
+
+fn tmux_session_exists_literal(target: &str) -> bool {
+    let output = Command::new("tmux")
+        .args(["list-sessions", "-F", "#{session_name}"])
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|session_name| session_name == target),
+        _ => false,
+    }
+}
+
+fn tmux_pane_target_exists(target: &str) -> bool {
+    Command::new("tmux")
+        .args(["display-message", "-p", "-t", target, "#{pane_id}"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+fn resolve_tmux_target(target: Option<String>) -> Result<String, String> {
+    let target = target.ok_or_else(|| {
+        "No target specified.\n\nUse --target TARGET.\nAsk the user which tmux target to use."
+            .to_string()
+    })?;
+
+    if is_special_tmux_target(&target) {
+        if tmux_pane_target_exists(&target) {
+            return Ok(target);
+        }
 
         return Err(format!(
             "Target '{}' not found.\n\nAsk the user which tmux target to use, or start a new bridge session with: tb start",
@@ -573,46 +2246,633 @@ fn resolve_tmux_target(target: Option<String>) -> Result<String, String> {
         ));
     }
 
-    if tmux_session_exists_literal(&target) {
-        return Ok(target);
+    if tmux_session_exists_literal(&target) {
+        return Ok(target);
+    }
+
+    let fallback_target = tmux_session_name(&target);
+    if tmux_session_exists_literal(&fallback_target) {
+        return Ok(fallback_target);
+    }
+
+    Err(format!(
+        "Target '{}' not found.\n\nAsk the user which tmux target to use, or start a new bridge session with: tb start",
+        target
+    ))
+}
+
+fn split_target(tmux_target: &str) -> Result<String, String> {
+    let output = Command::new("tmux")
+        .args(["display-message", "-p", "-t", tmux_target, "#{pane_id}"])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
+
+    if !output.status.success() {
+        return Err("Failed to inspect tmux target.".to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Capture the pane's full scrollback, joining lines the pane soft-wrapped
+/// (`-J`) so long lines come out whole; `styled` keeps SGR sequences (`-e`).
+fn capture_pane_scrollback(
+    pane_target: &str,
+    styled: bool,
+) -> Result<std::process::Output, String> {
+    let mut output = Command::new("tmux")
+        .args([
+            "capture-pane",
+            "-t",
+            pane_target,
+            "-p",
+            "-J",
+            "-S",
+            "-32768", // Capture full scrollback
+        ])
+        .args(styled.then_some("-e"))
+        .output()
+        .map_err(|e| format!("Failed to capture pane: {}", e))?;
+    output.stdout = trim_line_ends(&String::from_utf8_lossy(&output.stdout)).into_bytes();
+    Ok(output)
+}
+
+/// `-J` keeps the trailing blanks of each line, which plain captures drop.
+fn trim_line_ends(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect()
+}
+
+/// Capture the full scrollback like `capture_pane_scrollback`, and count the
+/// lines that are complete: every line before the one the cursor is on.
+///
+/// The cursor is read in the same tmux command as the capture, so output
+/// can't arrive in between. Rows below the cursor are blank and never
+/// wrapped, so each is exactly one line at the end of the joined capture.
+fn capture_pane_with_cursor(tmux_target: &str, styled: bool) -> Result<CapturedText, String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{pane_height} #{cursor_y}",
+            ";",
+            "capture-pane",
+            "-t",
+            tmux_target,
+            "-p",
+            "-J",
+            "-S",
+            "-32768",
+        ])
+        .args(styled.then_some("-e"))
+        .output()
+        .map_err(|e| format!("Failed to capture pane: {}", e))?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let parsed = text.split_once('\n').and_then(|(header, capture)| {
+        let mut parts = header.split_whitespace().map(|part| part.parse::<usize>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(height)), Some(Ok(cursor_y))) => Some((height, cursor_y, capture)),
+            _ => None,
+        }
+    });
+    let Some((height, cursor_y, capture)) = parsed.filter(|_| output.status.success()) else {
+        return Err("Failed to capture pane.".to_string());
+    };
+
+    let content = trim_line_ends(capture);
+    let below_cursor = height.saturating_sub(cursor_y + 1);
+    let complete_lines = content.lines().count().saturating_sub(below_cursor + 1);
+    Ok(CapturedText {
+        content,
+        complete_lines,
+    })
+}
+
+/// Where `tb run` reads pane text from while it waits for the end marker.
+enum OutputSource {
+    Scrollback,
+    PipeLog(std::path::PathBuf),
+}
+
+/// Pane text plus how many of its leading lines are known to be complete.
+struct CapturedText {
+    content: String,
+    complete_lines: usize,
+}
+
+impl OutputSource {
+    /// Read the current pane text, with SGR sequences when `styled`. The
+    /// complete-line count is only computed when streaming, since it needs
+    /// the cursor position for scrollback.
+    fn read(&self, tmux_target: &str, stream: bool, styled: bool) -> Result<CapturedText, String> {
+        match self {
+            Self::Scrollback if stream => capture_pane_with_cursor(tmux_target, styled),
+            Self::Scrollback => {
+                let output = capture_pane_scrollback(tmux_target, styled)?;
+                Ok(CapturedText {
+                    content: String::from_utf8_lossy(&output.stdout).into_owned(),
+                    complete_lines: 0,
+                })
+            }
+            Self::PipeLog(log_path) => {
+                let bytes = std::fs::read(log_path).unwrap_or_default();
+                Ok(render_terminal_log(&bytes, styled))
+            }
+        }
+    }
+}
+
+fn pipe_log_path(name: &str) -> std::path::PathBuf {
+    env::temp_dir().join(format!("tb-{}.log", name))
+}
+
+/// `$XDG_STATE_HOME/tb`, or `~/.local/state/tb` when that is unset.
+fn state_dir() -> Option<std::path::PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME")
+        .map(std::path::PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local/state"))
+        })?;
+    Some(base.join("tb"))
+}
+
+fn run_output_path(run_id: &str) -> Option<std::path::PathBuf> {
+    Some(state_dir()?.join("runs").join(format!("{}.log", run_id)))
+}
+
+/// How many saved run outputs to keep. Saving one more removes the oldest.
+const SAVED_RUNS_KEPT: usize = 50;
+
+/// Save the full output of a run that is too long to print whole, so the
+/// lines the truncation banner drops can be read with `tb output`. It is
+/// saved as plain text whatever `--ansi` prints, and only once per run.
+///
+/// Returns the run ID when the output was saved.
+fn save_run_output<'a>(
+    run_id: &'a str,
+    output: &str,
+    first: usize,
+    last: usize,
+) -> Option<&'a str> {
+    if output.lines().count() <= first + last {
+        return None;
     }
 
-    let fallback_target = tmux_session_name(&target);
-    if tmux_session_exists_literal(&fallback_target) {
-        return Ok(fallback_target);
+    let path = run_output_path(run_id)?;
+    if !path.exists() {
+        let runs_dir = path.parent()?;
+        std::fs::create_dir_all(runs_dir).ok()?;
+        create_private_file(&path, format!("{}\n", strip_ansi(output)).as_bytes()).ok()?;
+        prune_saved_runs(runs_dir);
     }
+    Some(run_id)
+}
 
-    Err(format!(
-        "Target '{}' not found.\n\nAsk the user which tmux target to use, or start a new bridge session with: tb start",
-        target
-    ))
+/// Remove all but the `SAVED_RUNS_KEPT` most recently saved outputs.
+fn prune_saved_runs(runs_dir: &std::path::Path) {
+    let Ok(entries) = std::fs::read_dir(runs_dir) else {
+        return;
+    };
+    let mut runs: Vec<(std::time::SystemTime, std::path::PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    if runs.len() <= SAVED_RUNS_KEPT {
+        return;
+    }
+    runs.sort();
+    for (_, path) in &runs[..runs.len() - SAVED_RUNS_KEPT] {
+        let _ = std::fs::remove_file(path);
+    }
 }
 
-fn split_target(tmux_target: &str) -> Result<String, String> {
+/// Start recording everything the pane prints into a fresh log file that
+/// only the user can read.
+///
+/// With `task_pane`, the pane exists only for the task, so the log is also
+/// removed once recording ends, which includes the pane being closed.
+fn start_pipe_log(
+    tmux_target: &str,
+    name: &str,
+    task_pane: bool,
+) -> Result<std::path::PathBuf, String> {
     let output = Command::new("tmux")
-        .args(["display-message", "-p", "-t", tmux_target, "#{pane_id}"])
+        .args(["display-message", "-p", "-t", tmux_target, "#{pane_pipe}"])
         .output()
         .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
 
-    if !output.status.success() {
-        return Err("Failed to inspect tmux target.".to_string());
+    if String::from_utf8_lossy(&output.stdout).trim() == "1" {
+        return Err(
+            "Target pane is already being recorded with pipe-pane.\n\nUse --capture scrollback, or ask the user to stop their pipe-pane."
+                .to_string(),
+        );
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    let log_path = pipe_log_path(name);
+    create_private_file(&log_path, b"")?;
+
+    let quoted_path = quote_shell_arg(&log_path.display().to_string());
+    let pipe_command = match task_pane {
+        true => format!("cat >> {0}; rm -f {0}", quoted_path),
+        false => format!("cat >> {}", quoted_path),
+    };
+    let status = Command::new("tmux")
+        .args(["pipe-pane", "-o", "-t", tmux_target, &pipe_command])
+        .status()
+        .map_err(|e| format!("Failed to start pipe-pane: {}", e))?;
+
+    if !status.success() {
+        let _ = std::fs::remove_file(&log_path);
+        return Err("Failed to start pipe-pane on target pane.".to_string());
+    }
+
+    Ok(log_path)
 }
 
-fn capture_pane_scrollback(pane_target: &str) -> Result<std::process::Output, String> {
-    Command::new("tmux")
-        .args([
-            "capture-pane",
-            "-t",
-            pane_target,
-            "-p",
-            "-S",
-            "-32768", // Capture full scrollback
-        ])
-        .output()
-        .map_err(|e| format!("Failed to capture pane: {}", e))
+/// Stop recording the pane and remove the log.
+fn stop_pipe_log(tmux_target: &str, log_path: &std::path::Path) {
+    stop_recording(tmux_target);
+    let _ = std::fs::remove_file(log_path);
+}
+
+/// Stop recording the pane but keep what the log holds so far.
+fn stop_recording(tmux_target: &str) {
+    let _ = Command::new("tmux")
+        .args(["pipe-pane", "-t", tmux_target])
+        .status();
+}
+
+/// Replay a raw terminal byte stream into plain text lines.
+///
+/// Handles the subset of terminal behaviour that matters for command output:
+/// carriage returns rewrite the current line (so progress bars collapse to
+/// their final state), backspace moves left, erase-in-line clears, and every
+/// other escape or control sequence is dropped. Lines are never split at the
+/// pane width. `tb;` OSC payloads are tb's own clean-display markers and come
+/// out as lines.
+fn render_terminal_log(bytes: &[u8], styled: bool) -> CapturedText {
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+    let mut lines: Vec<String> = Vec::new();
+    // Each cell holds a character and an index into `styles`, the SGR
+    // sequences in effect when it was written. Style 0 is the default.
+    let mut line: Vec<(char, usize)> = Vec::new();
+    let mut styles = vec![String::new()];
+    let mut style = 0;
+    let mut col: usize = 0;
+    let finish = |line: &[(char, usize)], styles: &[String]| render_cells(line, styles, styled);
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                lines.push(finish(&line, &styles));
+                line.clear();
+                col = 0;
+            }
+            '\r' => col = 0,
+            '\x08' => col = col.saturating_sub(1),
+            '\t' => col = (col / 8 + 1) * 8,
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    let mut action = None;
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            action = Some(c);
+                            break;
+                        }
+                        params.push(c);
+                    }
+                    let count = params.parse::<usize>().unwrap_or(1).max(1);
+                    match action {
+                        Some('K') => match params.as_str() {
+                            "1" => line
+                                .iter_mut()
+                                .take(col + 1)
+                                .for_each(|cell| *cell = (' ', 0)),
+                            "2" => line.clear(),
+                            _ => line.truncate(col),
+                        },
+                        Some('C') => col += count,
+                        Some('D') => col = col.saturating_sub(count),
+                        Some('G') => col = count - 1,
+                        Some('m') if styled => {
+                            style = match params.as_str() {
+                                "" | "0" => 0,
+                                _ => {
+                                    // A leading reset makes earlier sequences moot.
+                                    let earlier = if params.starts_with("0;") {
+                                        ""
+                                    } else {
+                                        styles[style].as_str()
+                                    };
+                                    styles.push(format!("{}\x1b[{}m", earlier, params));
+                                    styles.len() - 1
+                                }
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+                Some(']') => {
+                    // OSC runs until BEL or ST (ESC \\).
+                    let mut payload = String::new();
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                        payload.push(c);
+                    }
+                    // Clean-display markers get a line of their own.
+                    if let Some(marker) = payload.strip_prefix("tb;") {
+                        if !line.is_empty() {
+                            lines.push(finish(&line, &styles));
+                            line.clear();
+                        }
+                        lines.push(marker.to_string());
+                        col = 0;
+                    }
+                }
+                Some('(' | ')') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            c if c.is_control() => {}
+            c => {
+                if col < line.len() {
+                    line[col] = (c, style);
+                } else {
+                    line.resize(col, (' ', 0));
+                    line.push((c, style));
+                }
+                col += 1;
+            }
+        }
+    }
+
+    // The last line is still being written unless the log ends with a
+    // newline or a marker.
+    let complete_lines = lines.len();
+    if !line.is_empty() {
+        lines.push(finish(&line, &styles));
+    }
+
+    CapturedText {
+        content: lines.join("\n"),
+        complete_lines,
+    }
+}
+
+/// Turn one rendered line of cells into text without trailing blanks,
+/// switching SGR sequences between cells when `styled`.
+fn render_cells(cells: &[(char, usize)], styles: &[String], styled: bool) -> String {
+    let len = cells
+        .iter()
+        .rposition(|&(c, _)| c != ' ')
+        .map_or(0, |idx| idx + 1);
+    let mut text = String::new();
+    let mut current = 0;
+    for &(c, style) in &cells[..len] {
+        if styled && style != current {
+            if current != 0 {
+                text.push_str("\x1b[0m");
+            }
+            text.push_str(&styles[style]);
+            current = style;
+        }
+        text.push(c);
+    }
+    if current != 0 {
+        text.push_str("\x1b[0m");
+    }
+    text
+}
+
+/// A character of text, or the parameters of an SGR sequence, as found by
+/// `scan_ansi`.
+enum AnsiPiece<'a> {
+    Char(char),
+    Sgr(&'a str),
+}
+
+/// Walk `text`, reporting plain characters (including newlines and tabs)
+/// and SGR sequences. Every other escape sequence and control character is
+/// skipped.
+fn scan_ansi<'a>(text: &'a str, mut on_piece: impl FnMut(AnsiPiece<'a>)) {
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some((idx, '[')) => {
+                    let start = idx + 1;
+                    for (end, c) in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            if c == 'm' {
+                                on_piece(AnsiPiece::Sgr(&text[start..end]));
+                            }
+                            break;
+                        }
+                    }
+                }
+                Some((_, ']')) => {
+                    while let Some((_, c)) = chars.next() {
+                        if c == '\x07'
+                            || (c == '\x1b' && chars.next_if(|&(_, c)| c == '\\').is_some())
+                        {
+                            break;
+                        }
+                    }
+                }
+                Some((_, '(' | ')')) => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\n' | '\t' => on_piece(AnsiPiece::Char(c)),
+            c if c.is_control() => {}
+            c => on_piece(AnsiPiece::Char(c)),
+        }
+    }
+}
+
+/// Plain text with every escape sequence and control character removed,
+/// apart from newlines and tabs.
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    scan_ansi(text, |piece| {
+        if let AnsiPiece::Char(c) = piece {
+            plain.push(c);
+        }
+    });
+    plain
+}
+
+/// `line` without escape sequences, for matching tb's markers in styled
+/// captures. Borrows when there is nothing to strip.
+fn plain_text(line: &str) -> std::borrow::Cow<'_, str> {
+    if line.chars().any(|c| c.is_control() && c != '\t') {
+        std::borrow::Cow::Owned(strip_ansi(line))
+    } else {
+        std::borrow::Cow::Borrowed(line)
+    }
+}
+
+/// The SGR attributes `ansi_to_html` turns into CSS.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct SgrStyle {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl SgrStyle {
+    fn apply(&mut self, params: &str) {
+        let codes: Vec<u32> = params
+            .split([';', ':'])
+            .map(|code| code.parse().unwrap_or(0))
+            .collect();
+        let mut codes = codes.iter().copied();
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(ansi_color(code - 30)),
+                38 => self.fg = extended_color(&mut codes),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(ansi_color(code - 40)),
+                48 => self.bg = extended_color(&mut codes),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(ansi_color(code - 90 + 8)),
+                100..=107 => self.bg = Some(ansi_color(code - 100 + 8)),
+                _ => {}
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if let Some(fg) = &self.fg {
+            css.push(format!("color:{}", fg));
+        }
+        if let Some(bg) = &self.bg {
+            css.push(format!("background-color:{}", bg));
+        }
+        if self.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity:0.7".to_string());
+        }
+        if self.italic {
+            css.push("font-style:italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration:underline".to_string());
+        }
+        css.join(";")
+    }
+}
+
+/// The 256-color palette as CSS: the 16 xterm colors, the 6x6x6 cube, then
+/// the gray ramp.
+fn ansi_color(index: u32) -> String {
+    const BASIC: [&str; 16] = [
+        "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+        "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+    ];
+    match index {
+        0..=15 => BASIC[index as usize].to_string(),
+        16..=231 => {
+            let level = |n: u32| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level(n / 6 % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let gray = 8 + (index.min(255) - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", gray, gray, gray)
+        }
+    }
+}
+
+/// Read the rest of a `38;5;N` or `38;2;R;G;B` color.
+fn extended_color(codes: &mut impl Iterator<Item = u32>) -> Option<String> {
+    match codes.next() {
+        Some(5) => codes.next().map(ansi_color),
+        Some(2) => {
+            let (r, g, b) = (codes.next()?, codes.next()?, codes.next()?);
+            Some(format!(
+                "#{:02x}{:02x}{:02x}",
+                r.min(255),
+                g.min(255),
+                b.min(255)
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Render SGR-styled text as HTML-escaped lines with inline-styled spans.
+/// Styles carry across lines, but every line closes its own spans so lines
+/// still map one to one.
+fn ansi_to_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut style = SgrStyle::default();
+    let mut open = false;
+    let open_span = |html: &mut String, style: &SgrStyle| {
+        let css = style.css();
+        if css.is_empty() {
+            return false;
+        }
+        html.push_str(&format!("<span style=\"{}\">", css));
+        true
+    };
+
+    scan_ansi(text, |piece| match piece {
+        AnsiPiece::Sgr(params) => {
+            if open {
+                html.push_str("</span>");
+            }
+            style.apply(params);
+            open = open_span(&mut html, &style);
+        }
+        AnsiPiece::Char('\n') => {
+            if open {
+                html.push_str("</span>");
+            }
+            html.push('\n');
+            open = open_span(&mut html, &style);
+        }
+        AnsiPiece::Char('&') => html.push_str("&amp;"),
+        AnsiPiece::Char('<') => html.push_str("&lt;"),
+        AnsiPiece::Char('>') => html.push_str("&gt;"),
+        AnsiPiece::Char('"') => html.push_str("&quot;"),
+        AnsiPiece::Char(c) => html.push(c),
+    });
+    if open {
+        html.push_str("</span>");
+    }
+    html
 }
 
 fn shell_kind_from_argv0(argv0: &str) -> ShellKind {
@@ -642,119 +2902,527 @@ fn probe_shell_assessment(tmux_target: &str) -> Result<ShellAssessment, String>
         .status()
         .map_err(|e| format!("Failed to send command to tmux: {}", e))?;
 
-    if !status.success() {
-        return Err("Failed to send command to tmux.".to_string());
+    if !status.success() {
+        return Err("Failed to send command to tmux.".to_string());
+    }
+
+    match wait_for_probe_signature(tmux_target, &probe_marker)? {
+        Some(kind) => Ok(ShellAssessment::confident(kind)),
+        None => Ok(ShellAssessment::unknown()),
+    }
+}
+
+fn probe_marker_command(marker: &str) -> String {
+    format!(
+        "printf '%s|%s|%s|%s\\n' {} \"$version\" \"$BASH_VERSION\" \"$0\"",
+        quote_shell_arg(marker)
+    )
+}
+
+fn wait_for_probe_signature(tmux_target: &str, marker: &str) -> Result<Option<ShellKind>, String> {
+    // Interactive shells wrapped behind another foreground process can take a
+    // little longer to repaint the probe line on busy CI runners.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let poll_interval = std::time::Duration::from_millis(100);
+    let marker_prefix = format!("{}|", marker);
+
+    while std::time::Instant::now() < deadline {
+        std::thread::sleep(poll_interval);
+        let output = capture_pane_scrollback(tmux_target, false)?;
+        let pane_content = String::from_utf8_lossy(&output.stdout);
+
+        for line in pane_content.lines() {
+            // Ignore echoed or wrapped command text that happens to contain the
+            // marker literal before the shell prints the expanded probe output.
+            if let Some((_, signature)) = line.trim().split_once(&marker_prefix)
+                && let Some(kind) = parse_probe_signature(signature)
+            {
+                return Ok(Some(kind));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn parse_probe_signature(signature: &str) -> Option<ShellKind> {
+    let mut parts = signature.splitn(3, '|');
+    let fish_version = parts.next().unwrap_or("");
+    let bash_version = parts.next().unwrap_or("");
+    let argv0 = parts.next().unwrap_or("");
+
+    if !fish_version.is_empty() {
+        return Some(ShellKind::Fish);
+    }
+
+    let argv0_kind = shell_kind_from_argv0(argv0);
+    if argv0_kind != ShellKind::Unknown {
+        return Some(argv0_kind);
+    }
+
+    if !bash_version.is_empty() {
+        return Some(ShellKind::Bash);
+    }
+
+    None
+}
+
+fn random_marker_id() -> String {
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| {
+            let chars: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+            chars[rng.gen_range(0..chars.len())] as char
+        })
+        .collect()
+}
+
+/// Optional extras wrapped around the injected command.
+#[derive(Default)]
+struct WrapOptions {
+    /// Send the command's stderr to a side file and replay it after a
+    /// `___STDERR_<id>___` marker, so it can be told apart from stdout.
+    split_stderr: bool,
+    /// Bytes for the command's stdin, which it reads from a side file that
+    /// tb writes before typing the wrapper.
+    stdin: Option<Vec<u8>>,
+    /// Clean display: erase this many rows of typed wrapper, print
+    /// `[agent] $ <command>`, and emit markers as OSC sequences that the
+    /// terminal swallows but pipe-pane still records.
+    clean_display: Option<usize>,
+    /// Run the command in a subshell, or a child `fish` for fish, so it
+    /// can't change the human's shell. `cwd` and `env` apply in there.
+    isolate: bool,
+    cwd: Option<String>,
+    env: Vec<(String, String)>,
+}
+
+/// Build the shell command with markers
+fn build_shell_command(
+    command: &[String],
+    marker_id: &str,
+    shell_kind: ShellKind,
+    wrap: &WrapOptions,
+) -> String {
+    let cmd_str = shell_command_text(command);
+
+    match shell_kind {
+        ShellKind::Fish | ShellKind::Bash | ShellKind::Sh => {
+            build_direct_shell_command(&cmd_str, marker_id, shell_kind, wrap)
+        }
+        ShellKind::Unknown => build_fallback_shell_command(&cmd_str, marker_id, wrap),
+    }
+}
+
+/// Build the wrapper for clean display mode.
+///
+/// The wrapper erases its own echo, so it has to know how many pane rows it
+/// takes up when typed at `prompt` (cursor column, pane width). That count
+/// is part of the wrapper text, so settle it by rebuilding until it agrees
+/// with itself.
+fn build_clean_shell_command(
+    command: &[String],
+    marker_id: &str,
+    shell_kind: ShellKind,
+    wrap: &mut WrapOptions,
+    prompt: (usize, usize),
+) -> String {
+    let mut erase_rows = 1;
+    loop {
+        wrap.clean_display = Some(erase_rows);
+        let shell_command = build_shell_command(command, marker_id, shell_kind, wrap);
+        let rows = typed_rows(&shell_command, prompt);
+        if rows <= erase_rows {
+            return shell_command;
+        }
+        erase_rows = rows;
+    }
+}
+
+/// Count the pane rows a command line fills once typed after the prompt.
+/// Continuation lines are assumed to sit behind a two-column `> ` prompt.
+fn typed_rows(text: &str, (prompt_col, pane_width): (usize, usize)) -> usize {
+    text.split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let start = if i == 0 { prompt_col } else { 2 };
+            (start + line.chars().count())
+                .div_ceil(pane_width.max(1))
+                .max(1)
+        })
+        .sum()
+}
+
+fn pane_cursor_and_width(tmux_target: &str) -> Result<(usize, usize), String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{cursor_x} #{pane_width}",
+        ])
+        .output()
+        .map_err(|e| format!("Failed to inspect tmux target: {}", e))?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.split_whitespace().map(str::parse::<usize>);
+    match (fields.next(), fields.next()) {
+        (Some(Ok(cursor_x)), Some(Ok(pane_width))) => Ok((cursor_x, pane_width)),
+        _ => Err("Failed to read the target pane's cursor position.".to_string()),
+    }
+}
+
+fn stdin_file(marker_id: &str) -> std::path::PathBuf {
+    env::temp_dir().join(format!("tb-{}.in", marker_id))
+}
+
+fn stderr_file(marker_id: &str) -> std::path::PathBuf {
+    env::temp_dir().join(format!("tb-{}.err", marker_id))
+}
+
+fn pipestatus_file(marker_id: &str) -> std::path::PathBuf {
+    env::temp_dir().join(format!("tb-{}.status", marker_id))
+}
+
+/// Create the side files a run's wrapper reads stdin from and sends stderr
+/// and an isolated command's stage statuses to. They are created fresh,
+/// readable only by the user, and removed by `remove_side_files`.
+fn create_side_files(marker_id: &str, wrap: &WrapOptions) -> Result<(), String> {
+    if let Some(bytes) = &wrap.stdin {
+        create_private_file(&stdin_file(marker_id), bytes)?;
+    }
+    if wrap.split_stderr {
+        create_private_file(&stderr_file(marker_id), b"")?;
+    }
+    if wrap.isolate {
+        create_private_file(&pipestatus_file(marker_id), b"")?;
+    }
+    Ok(())
+}
+
+/// Remove a run's side files, whether it finished, failed, or timed out.
+fn remove_side_files(marker_id: &str) {
+    let _ = std::fs::remove_file(stdin_file(marker_id));
+    let _ = std::fs::remove_file(stderr_file(marker_id));
+    let _ = std::fs::remove_file(pipestatus_file(marker_id));
+}
+
+/// Create a new file only the current user can read. It must not exist yet,
+/// so a file or symlink planted under the same name is never written to.
+fn create_private_file(path: &std::path::Path, contents: &[u8]) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Quote a side file's path for the pane shell.
+fn quote_path(path: &std::path::Path, fish: bool) -> String {
+    let path = path.display().to_string();
+    if fish && !is_bare_shell_arg(&path) {
+        quote_fish_arg(&path)
+    } else {
+        quote_shell_arg(&path)
+    }
+}
+
+/// Wrap the command text with markers in the pane shell's own syntax.
+///
+/// Without extras this is just `echo START; command; echo END_$?`. Extras that
+/// need work after the command (such as replaying stderr) save the exit
+/// status first, so the end marker still reports the command's own status.
+fn build_direct_shell_command(
+    command_text: &str,
+    marker_id: &str,
+    shell_kind: ShellKind,
+    wrap: &WrapOptions,
+) -> String {
+    let fish = shell_kind == ShellKind::Fish;
+    let mut redirects = String::new();
+    let mut epilogue = Vec::new();
+
+    if wrap.stdin.is_some() {
+        redirects.push_str(&format!(" <{}", quote_path(&stdin_file(marker_id), fish)));
     }
 
-    match wait_for_probe_signature(tmux_target, &probe_marker)? {
-        Some(kind) => Ok(ShellAssessment::confident(kind)),
-        None => Ok(ShellAssessment::unknown()),
+    // tb removes the stderr file once the run is over, which can be before
+    // a killed command's wrapper gets to replay it.
+    if wrap.split_stderr {
+        let err_file = quote_path(&stderr_file(marker_id), fish);
+        redirects.push_str(&format!(" 2>{err_file}"));
+        epilogue.push(marker_command(&format!("___STDERR_{marker_id}___"), wrap));
+        epilogue.push(format!("cat {err_file} 2>/dev/null"));
     }
-}
 
-fn probe_marker_command(marker: &str) -> String {
-    format!(
-        "printf '%s|%s|%s|%s\\n' {} \"$version\" \"$BASH_VERSION\" \"$0\"",
-        quote_shell_arg(marker)
-    )
-}
+    // An isolated command's stages run out of the pane shell's sight, so
+    // the child writes their statuses to a side file for the end marker.
+    let stages_file = (wrap.isolate && matches!(shell_kind, ShellKind::Bash | ShellKind::Fish))
+        .then(|| quote_path(&pipestatus_file(marker_id), fish));
+    let body_text = if wrap.isolate {
+        isolated_command(command_text, fish, stages_file.as_deref(), wrap)
+    } else {
+        command_text.to_string()
+    };
+    let body = match (redirects.is_empty(), fish) {
+        (true, _) => body_text,
+        (false, true) => format!("begin; {body_text}; end{redirects}"),
+        (false, false) => format!("{{ {body_text}; }}{redirects}"),
+    };
 
-fn wait_for_probe_signature(tmux_target: &str, marker: &str) -> Result<Option<ShellKind>, String> {
-    // Interactive shells wrapped behind another foreground process can take a
-    // little longer to repaint the probe line on busy CI runners.
-    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
-    let poll_interval = std::time::Duration::from_millis(100);
-    let marker_prefix = format!("{}|", marker);
+    let mut parts = Vec::new();
+    if let Some(erase_rows) = wrap.clean_display {
+        parts.push(format!(
+            "printf '\\033[{erase_rows}A\\r\\033[J%b\\n' '{}'",
+            encode_shell_bytes(format!("[agent] $ {command_text}").as_bytes())
+        ));
+    }
+    parts.push(marker_command(&format!("___START_{marker_id}___"), wrap));
+    parts.push(body);
+    // bash and fish also report each pipeline stage's status. Saving them
+    // has to happen in the same command as saving `$?`.
+    if let Some(stages_file) = &stages_file {
+        let (save_status, exit_status, clear_status) = if fish {
+            (
+                format!(
+                    "set -l __tb_status $status; set -a __tb_status (cat {stages_file} 2>/dev/null)"
+                ),
+                ("{$__tb_status[1]}", Some("\"$__tb_status[2..-1]\"")),
+                "set -e __tb_status",
+            )
+        } else {
+            (
+                format!("__tb_status=$?; __tb_pipestatus=\"$(cat {stages_file} 2>/dev/null)\""),
+                ("${__tb_status}", Some("\"${__tb_pipestatus}\"")),
+                "unset __tb_status __tb_pipestatus",
+            )
+        };
+        parts.push(save_status);
+        parts.extend(epilogue);
+        parts.push(end_marker_command(marker_id, exit_status, wrap));
+        parts.push(clear_status.to_string());
+    } else if epilogue.is_empty() {
+        let exit_status = match shell_kind {
+            ShellKind::Fish => ("{$status}", Some("\"$pipestatus\"")),
+            ShellKind::Bash => ("$?", Some("\"${PIPESTATUS[*]}\"")),
+            ShellKind::Sh | ShellKind::Unknown => ("$?", None),
+        };
+        parts.push(end_marker_command(marker_id, exit_status, wrap));
+    } else {
+        // The saved statuses are cleared again so they don't linger in the
+        // human's shell.
+        let (save_status, exit_status, clear_status) = match shell_kind {
+            ShellKind::Fish => (
+                "set -l __tb_status $status $pipestatus",
+                ("{$__tb_status[1]}", Some("\"$__tb_status[2..-1]\"")),
+                "set -e __tb_status",
+            ),
+            ShellKind::Bash => (
+                "__tb_status=$? __tb_pipestatus=\"${PIPESTATUS[*]}\"",
+                ("${__tb_status}", Some("\"${__tb_pipestatus}\"")),
+                "unset __tb_status __tb_pipestatus",
+            ),
+            ShellKind::Sh | ShellKind::Unknown => (
+                "__tb_status=$?",
+                ("${__tb_status}", None),
+                "unset __tb_status",
+            ),
+        };
+        parts.push(save_status.to_string());
+        parts.extend(epilogue);
+        parts.push(end_marker_command(marker_id, exit_status, wrap));
+        parts.push(clear_status.to_string());
+    }
 
-    while std::time::Instant::now() < deadline {
-        std::thread::sleep(poll_interval);
-        let output = capture_pane_scrollback(tmux_target)?;
-        let pane_content = String::from_utf8_lossy(&output.stdout);
+    parts.join("; ")
+}
 
-        for line in pane_content.lines() {
-            // Ignore echoed or wrapped command text that happens to contain the
-            // marker literal before the shell prints the expanded probe output.
-            if let Some((_, signature)) = line.trim().split_once(&marker_prefix)
-                && let Some(kind) = parse_probe_signature(signature)
-            {
-                return Ok(Some(kind));
-            }
+/// Run `command_text` where it can't touch the human's shell: a `( ... )`
+/// subshell for POSIX shells, or a child `fish -c` since fish has no
+/// subshells. The exit status is the command's own, or the failed `cd`'s.
+///
+/// From outside, the pane shell sees one status for the whole thing, so
+/// bash and fish write the pipeline's stage statuses to `stages_file`.
+fn isolated_command(
+    command_text: &str,
+    fish: bool,
+    stages_file: Option<&str>,
+    wrap: &WrapOptions,
+) -> String {
+    let mut parts = Vec::new();
+    if fish {
+        if let Some(cwd) = &wrap.cwd {
+            parts.push(format!("cd {}; or exit", quote_fish_arg(cwd)));
+        }
+        for (key, value) in &wrap.env {
+            parts.push(format!("set -gx {} {}", key, quote_fish_arg(value)));
+        }
+        parts.push(command_text.to_string());
+        if let Some(stages_file) = stages_file {
+            parts.push("set -l __tb_status $status $pipestatus".to_string());
+            parts.push(format!("echo \"$__tb_status[2..-1]\" >{stages_file}"));
+            parts.push("exit $__tb_status[1]".to_string());
+        }
+        format!("fish -c {}", quote_fish_arg(&parts.join("; ")))
+    } else {
+        if let Some(cwd) = &wrap.cwd {
+            parts.push(format!("cd -- {} || exit", quote_shell_arg(cwd)));
+        }
+        for (key, value) in &wrap.env {
+            parts.push(format!("export {}={}", key, quote_shell_arg(value)));
         }
+        parts.push(command_text.to_string());
+        if let Some(stages_file) = stages_file {
+            parts.push("__tb_status=$? __tb_pipestatus=\"${PIPESTATUS[*]}\"".to_string());
+            parts.push(format!("echo \"$__tb_pipestatus\" >{stages_file}"));
+            parts.push("exit $__tb_status".to_string());
+        }
+        format!("( {} )", parts.join("; "))
     }
-
-    Ok(None)
 }
 
-fn parse_probe_signature(signature: &str) -> Option<ShellKind> {
-    let mut parts = signature.splitn(3, '|');
-    let fish_version = parts.next().unwrap_or("");
-    let bash_version = parts.next().unwrap_or("");
-    let argv0 = parts.next().unwrap_or("");
+/// Quote one word for fish, whose quotes only know `\\` and `\'` escapes.
+fn quote_fish_arg(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
 
-    if !fish_version.is_empty() {
-        return Some(ShellKind::Fish);
+/// Parse a `KEY=VALUE` pair for `--env`.
+fn parse_env_var(text: &str) -> Result<(String, String), String> {
+    let (key, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("invalid variable '{}' (use KEY=VALUE)", text))?;
+    let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_key {
+        return Err(format!("invalid variable name '{}'", key));
     }
+    Ok((key.to_string(), value.to_string()))
+}
 
-    let argv0_kind = shell_kind_from_argv0(argv0);
-    if argv0_kind != ShellKind::Unknown {
-        return Some(argv0_kind);
+/// What `--profile noninteractive` sets: no pagers, no colors, and no
+/// installer questions waiting on a terminal nobody is watching.
+const NONINTERACTIVE_PROFILE: &[(&str, &str)] = &[
+    ("PAGER", "cat"),
+    ("GIT_PAGER", "cat"),
+    ("SYSTEMD_PAGER", ""),
+    ("MANPAGER", "cat"),
+    ("LESS", "-FRX"),
+    ("NO_COLOR", "1"),
+    ("DEBIAN_FRONTEND", "noninteractive"),
+];
+
+/// The variables `--profile NAME` sets. Every profile starts from the
+/// `noninteractive` set; a `profiles/NAME` file in tb's config directory
+/// then sets variables with `KEY=VALUE` lines and drops them with `-KEY`
+/// lines (blank lines and `#` comments are skipped).
+fn load_env_profile(name: &str) -> Result<Vec<(String, String)>, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("invalid profile name '{}'", name));
     }
+    let mut vars: Vec<(String, String)> = NONINTERACTIVE_PROFILE
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    let path = config_dir().map(|dir| dir.join("profiles").join(name));
+    let file = path
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok());
+    let (Some(path), Some(file)) = (&path, file) else {
+        if name == "noninteractive" {
+            return Ok(vars);
+        }
+        return Err(format!(
+            "Profile '{}' not found.\n\nCreate {} with KEY=VALUE lines to set variables and -KEY lines to drop them, or use --profile noninteractive.",
+            name,
+            path.map_or_else(
+                || format!("~/.config/tb/profiles/{}", name),
+                |path| path.display().to_string()
+            )
+        ));
+    };
 
-    if !bash_version.is_empty() {
-        return Some(ShellKind::Bash);
+    for (index, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(key) = line.strip_prefix('-') {
+            vars.retain(|(set, _)| set != key);
+            continue;
+        }
+        let (key, value) = parse_env_var(line)
+            .map_err(|e| format!("{} line {}: {}", path.display(), index + 1, e))?;
+        vars.retain(|(set, _)| *set != key);
+        vars.push((key, value));
     }
-
-    None
-}
-
-fn random_marker_id() -> String {
-    let mut rng = rand::thread_rng();
-    (0..8)
-        .map(|_| {
-            let chars: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
-            chars[rng.gen_range(0..chars.len())] as char
-        })
-        .collect()
+    Ok(vars)
 }
 
-/// Build the shell command with markers
-fn build_shell_command(command: &[String], marker_id: &str, shell_kind: ShellKind) -> String {
-    let cmd_str = shell_command_text(command);
-
-    match shell_kind {
-        ShellKind::Fish => build_direct_shell_command(&cmd_str, marker_id, "{$status}"),
-        ShellKind::Bash | ShellKind::Sh => build_direct_shell_command(&cmd_str, marker_id, "$?"),
-        ShellKind::Unknown => build_fallback_shell_command(&cmd_str, marker_id),
+/// Print a marker line: a plain `echo`, or an invisible OSC sequence in
+/// clean display mode.
+fn marker_command(marker: &str, wrap: &WrapOptions) -> String {
+    if wrap.clean_display.is_some() {
+        format!("printf '\\033]tb;{marker}\\007'")
+    } else {
+        format!("echo {marker}")
     }
 }
 
-fn build_direct_shell_command(command_text: &str, marker_id: &str, exit_status: &str) -> String {
-    format!(
-        "echo ___START_{marker_id}___; {command_text}; echo ___END_{marker_id}_{exit_status}___"
-    )
+/// Print the end marker carrying the exit status and, after a `:`, any
+/// per-stage pipeline statuses. Both are written as they would appear inside
+/// an `echo` word (`$?`, `{$status}`, `"${PIPESTATUS[*]}"`).
+fn end_marker_command(
+    marker_id: &str,
+    (exit_status, pipestatus): (&str, Option<&str>),
+    wrap: &WrapOptions,
+) -> String {
+    if wrap.clean_display.is_some() {
+        // As a separate printf argument the variable needs no braces.
+        let exit_status = exit_status.replace(['{', '}'], "");
+        match pipestatus {
+            Some(pipestatus) => format!(
+                "printf '\\033]tb;___END_{marker_id}_%s:%s___\\007' {exit_status} {pipestatus}"
+            ),
+            None => format!("printf '\\033]tb;___END_{marker_id}_%s___\\007' {exit_status}"),
+        }
+    } else {
+        match pipestatus {
+            Some(pipestatus) => format!("echo ___END_{marker_id}_{exit_status}:{pipestatus}___"),
+            None => format!("echo ___END_{marker_id}_{exit_status}___"),
+        }
+    }
 }
 
-fn build_fallback_shell_command(command_text: &str, marker_id: &str) -> String {
+fn build_fallback_shell_command(command_text: &str, marker_id: &str, wrap: &WrapOptions) -> String {
     // Build the inner script that will run inside sh -c
     // This script: echoes start marker, runs command, echoes end marker with exit status.
     // Markers use only alphanumeric characters and underscores, so we keep them bare.
-    let inner_script = format!(
-        "echo ___START_{}___; {}; echo ___END_{}_$?___",
-        marker_id, command_text, marker_id
-    );
+    let inner_script = build_direct_shell_command(command_text, marker_id, ShellKind::Sh, wrap);
 
     // Transport the inner script through the active shell as an octal-escaped
     // byte stream, then let a fresh POSIX shell parse it. This keeps fallback
     // behavior consistent across fish and POSIX shells without corrupting the
     // exact script text that the inner `sh` should see.
-    let encoded_script = encode_shell_bytes(&inner_script);
+    let encoded_script = encode_shell_bytes(inner_script.as_bytes());
 
     format!("printf '%b' '{}' | sh", encoded_script)
 }
 
-fn encode_shell_bytes(s: &str) -> String {
-    let mut encoded = String::with_capacity(s.len() * 4);
-    for byte in s.bytes() {
+fn encode_shell_bytes(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 4);
+    for &byte in bytes {
         encoded.push('\\');
         encoded.push_str(&format!("{:03o}", byte));
     }
@@ -820,83 +3488,647 @@ fn escape_for_double_quotes(s: &str) -> String {
 
 /// Find exit code from end marker in output
 fn find_exit_code(content: &str, end_marker_prefix: &str) -> Option<i32> {
+    find_end_status(content, end_marker_prefix).map(|(code, _)| code)
+}
+
+/// Find the exit code and per-stage pipeline statuses in the end marker.
+fn find_end_status(content: &str, end_marker_prefix: &str) -> Option<(i32, Vec<i32>)> {
     for line in content.lines() {
-        if let Some(rest) = line.strip_prefix(end_marker_prefix) {
-            // Format: ___END_{id}_{exit_code}___
-            if let Some(end) = rest.find("___")
-                && let Ok(code) = rest[..end].parse::<i32>()
-            {
-                return Some(code);
+        if let Some(rest) = plain_text(line).strip_prefix(end_marker_prefix) {
+            // Format: ___END_{id}_{exit_code}___ or ___END_{id}_{exit_code}:{stages}___
+            let Some(end) = rest.find("___") else {
+                continue;
+            };
+            let (code, stages) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+            let stages: Option<Vec<i32>> =
+                stages.split_whitespace().map(|s| s.parse().ok()).collect();
+            if let (Ok(code), Some(stages)) = (code.parse::<i32>(), stages) {
+                return Some((code, stages));
             }
         }
     }
     None
 }
 
+/// Signals that have the same number on Linux and macOS.
+const SIGNAL_NAMES: &[(i32, &str)] = &[
+    (1, "HUP"),
+    (2, "INT"),
+    (3, "QUIT"),
+    (4, "ILL"),
+    (5, "TRAP"),
+    (6, "ABRT"),
+    (8, "FPE"),
+    (9, "KILL"),
+    (11, "SEGV"),
+    (13, "PIPE"),
+    (14, "ALRM"),
+    (15, "TERM"),
+];
+
+/// Name the signal behind a shell exit status of 128+N.
+fn signal_from_status(status: i32) -> Option<&'static str> {
+    SIGNAL_NAMES
+        .iter()
+        .find(|&&(number, _)| number + 128 == status)
+        .map(|&(_, name)| name)
+}
+
 /// Extract output between start and end markers
 fn extract_output(content: &str, start_marker: &str, end_marker_prefix: &str) -> String {
     let lines: Vec<&str> = content.lines().collect();
     let mut start_idx = None;
     let mut end_idx = None;
 
-    for (i, line) in lines.iter().enumerate() {
-        // Match lines that start with the marker (not just contain it)
-        if start_idx.is_none() && line.starts_with(start_marker) {
-            start_idx = Some(i + 1); // Start after the marker line
-        } else if start_idx.is_some() && line.starts_with(end_marker_prefix) {
-            end_idx = Some(i);
-            break;
+    for (i, line) in lines.iter().enumerate() {
+        // Match lines that start with the marker (not just contain it)
+        if start_idx.is_none() && plain_text(line).starts_with(start_marker) {
+            start_idx = Some(i + 1); // Start after the marker line
+        } else if start_idx.is_some() && plain_text(line).starts_with(end_marker_prefix) {
+            end_idx = Some(i);
+            break;
+        }
+    }
+
+    match (start_idx, end_idx) {
+        (Some(s), Some(e)) if s < e => lines[s..e].join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Extract output after the start marker of a command that has not finished.
+fn extract_partial_output(content: &str, start_marker: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    match lines
+        .iter()
+        .position(|line| plain_text(line).starts_with(start_marker))
+    {
+        Some(start) => lines[start + 1..].join("\n").trim_end().to_string(),
+        None => String::new(),
+    }
+}
+
+/// Print output with truncation if needed
+fn print_output(output: &str, first: usize, last: usize, saved_run: Option<&str>) {
+    print!("{}", truncate_output(output, first, last, saved_run));
+}
+
+/// Render output exactly as `print_output` shows it, keeping only the first
+/// and last lines when it is longer than the window. `saved_run` names the
+/// run whose full output `save_run_output` kept, for the banner to point at.
+fn truncate_output(output: &str, first: usize, last: usize, saved_run: Option<&str>) -> String {
+    let lines: Vec<&str> = output.lines().collect();
+    let total = lines.len();
+
+    if total <= first + last {
+        // No truncation needed
+        return format!("{}\n", output);
+    }
+
+    let mut text = String::new();
+
+    // First N lines
+    for line in lines.iter().take(first) {
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    let truncated = total - first - last;
+    text.push_str(&format!("\n... ({} lines truncated) ...\n", truncated));
+    if let Some(run_id) = saved_run
+        && let Some(path) = run_output_path(run_id)
+    {
+        text.push_str(&format!("Full output saved to {}\n", path.display()));
+        text.push_str(&format!(
+            "Page through it with: tb output {} --range {}:{}\n",
+            run_id,
+            first + 1,
+            first + truncated
+        ));
+    }
+    text.push('\n');
+
+    // Last N lines
+    for line in lines.iter().skip(total - last) {
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Split captured command output at the stderr replay marker.
+fn split_stderr_output(output: &str, stderr_marker: &str) -> (String, Option<String>) {
+    let lines: Vec<&str> = output.lines().collect();
+    match lines
+        .iter()
+        .position(|line| plain_text(line).starts_with(stderr_marker))
+    {
+        Some(idx) => (lines[..idx].join("\n"), Some(lines[idx + 1..].join("\n"))),
+        None => (output.to_string(), None),
+    }
+}
+
+/// Print command output lines that completed since the previous poll.
+///
+/// Returns the new total of streamed lines. Only the first `complete_lines`
+/// lines of the capture are considered, so a line still being written at the
+/// cursor waits for a later poll instead of being printed half-finished.
+fn stream_new_lines(
+    pane_content: &str,
+    complete_lines: usize,
+    start_marker: &str,
+    stderr_marker: &str,
+    streamed: usize,
+) -> usize {
+    let lines: Vec<&str> = pane_content.lines().take(complete_lines).collect();
+    let Some(start_idx) = lines
+        .iter()
+        .position(|line| plain_text(line).starts_with(start_marker))
+    else {
+        return streamed;
+    };
+
+    // Replayed stderr goes to tb's stderr once the command finishes.
+    let available: Vec<&str> = lines[start_idx + 1..]
+        .iter()
+        .take_while(|line| !plain_text(line).starts_with(stderr_marker))
+        .copied()
+        .collect();
+    for line in available.iter().skip(streamed) {
+        println!("{}", line);
+    }
+
+    streamed.max(available.len())
+}
+
+/// Print whatever the stream has not shown yet, then a truncated summary when
+/// the full output is longer than the `--first`/`--last` window.
+fn finish_stream(
+    output: &str,
+    streamed: usize,
+    first: usize,
+    last: usize,
+    saved_run: Option<&str>,
+) {
+    let lines: Vec<&str> = output.lines().collect();
+    for line in lines.iter().skip(streamed) {
+        println!("{}", line);
+    }
+
+    if lines.len() > first + last {
+        println!("\n--- Summary ({} lines) ---\n", lines.len());
+        print_output(output, first, last, saved_run);
+    }
+}
+
+/// How long the output must sit still before its last line counts as a
+/// prompt, so a line that merely mentions a password is not mistaken for one.
+const PROMPT_SETTLE_TIME: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Prompts a command can block on until the human answers: passwords,
+/// passphrases and yes/no confirmations. Each is matched against the last
+/// line of output; `$XDG_CONFIG_HOME/tb/prompt-patterns` adds more.
+const BUILTIN_PROMPT_PATTERNS: &[&str] = &[
+    r"^\[sudo\] password for .*:\s*$",
+    r"(?i)enter passphrase.*:\s*$",
+    r"(?i)password.*:\s*$",
+    r"\(yes/no(/\[fingerprint\])?\)\??\s*$",
+    r"(?i)\[y/n\]\s*[?:]?\s*$",
+];
+
+fn config_dir() -> Option<std::path::PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config")))?;
+    Some(base.join("tb"))
+}
+
+/// The built-in prompt patterns plus any in the user's `prompt-patterns`
+/// file, one regex per line (blank lines and `#` comments are skipped).
+fn load_prompt_patterns() -> Result<Vec<regex::Regex>, String> {
+    let mut patterns: Vec<regex::Regex> = BUILTIN_PROMPT_PATTERNS
+        .iter()
+        .map(|pattern| regex::Regex::new(pattern).expect("built-in prompt pattern"))
+        .collect();
+
+    let Some(path) = config_dir().map(|dir| dir.join("prompt-patterns")) else {
+        return Ok(patterns);
+    };
+    let Ok(config) = std::fs::read_to_string(&path) else {
+        return Ok(patterns);
+    };
+    for (index, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pattern = regex::Regex::new(line).map_err(|e| {
+            format!(
+                "{} line {}: invalid pattern: {}",
+                path.display(),
+                index + 1,
+                e
+            )
+        })?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+/// The command's last line of output, if it is a prompt waiting for the
+/// human.
+fn find_waiting_prompt(output: &str, patterns: &[regex::Regex]) -> Option<String> {
+    let last_line = output.lines().last()?;
+    let last_line = plain_text(last_line).trim_end().to_string();
+    patterns
+        .iter()
+        .any(|pattern| pattern.is_match(&last_line))
+        .then_some(last_line)
+}
+
+/// Programs that page output and wait for keys. `--quit-pager` quits them
+/// with `q`.
+const PAGER_PROGRAMS: &[&str] = &["less", "more", "most", "pg"];
+
+/// A pager or full-screen program that has taken the pane over from the
+/// command's own output, so the end marker cannot show up until someone
+/// quits it.
+fn find_screen_takeover(tmux_target: &str) -> Option<Blocker> {
+    let foreground = pane_foreground(tmux_target).ok()?;
+    // The pager need not be the job's newest process, e.g. when the `sh`
+    // running the command forked it in the same instant.
+    let programs = match foreground.pgid {
+        Some(pgid) => processes_in_group(pgid),
+        None => vec![foreground.program.clone()],
+    };
+    if let Some(pager) = programs
+        .into_iter()
+        .find(|program| PAGER_PROGRAMS.contains(&program.as_str()))
+    {
+        return Some(Blocker::Pager(pager));
+    }
+
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            tmux_target,
+            "#{alternate_on}",
+        ])
+        .output()
+        .ok()?;
+    (String::from_utf8_lossy(&output.stdout).trim() == "1")
+        .then_some(Blocker::FullScreen(foreground.program))
+}
+
+/// Stop waiting on a command that waits for the human, without interrupting
+/// it: it keeps running as a task, like `--on-timeout detach`, so the human
+/// can deal with it and the agent can check on it afterwards.
+fn finish_blocked_run(
+    watch: &RunWatch,
+    streamed_lines: usize,
+    start_time: std::time::Instant,
+    blocker: Blocker,
+) -> RunReport {
+    let snapshot = take_timeout_snapshot(watch);
+    let task_id = detach_run(watch)
+        .map_err(|e| eprintln!("Could not track the command as a task: {}", e))
+        .ok();
+
+    RunReport {
+        output: snapshot.output,
+        stderr: None,
+        exit_code: None,
+        pipestatus: Vec::new(),
+        timeout: None,
+        blocked: Some(BlockedReport {
+            blocker,
+            task_id,
+            screen: snapshot.screen,
+        }),
+        actions: HumanActions::default(),
+        streamed_lines,
+        duration: start_time.elapsed(),
+    }
+}
+
+/// Deal with a command that hit a timeout: detach it or kill it, and report
+/// what it had output before either.
+///
+/// `advice` only matters when the command is killed; a detached command is
+/// still doing its job.
+fn finish_timed_out_run(
+    watch: &RunWatch,
+    streamed_lines: usize,
+    start_time: std::time::Instant,
+    kind: &'static str,
+    reason: String,
+    advice: Vec<String>,
+) -> RunReport {
+    let snapshot = take_timeout_snapshot(watch);
+    let duration = start_time.elapsed();
+
+    let mut task_id = None;
+    if watch.on_timeout == OnTimeout::Detach {
+        match detach_run(watch) {
+            Ok(id) => task_id = Some(id),
+            Err(e) => eprintln!("Could not detach the command, stopping it instead: {}", e),
+        }
+    }
+    let stopped_by = if task_id.is_none() {
+        kill_running_command(watch)
+    } else {
+        None
+    };
+
+    RunReport {
+        output: snapshot.output,
+        stderr: None,
+        exit_code: None,
+        pipestatus: Vec::new(),
+        timeout: Some(TimeoutReport {
+            kind,
+            reason,
+            advice,
+            task_id,
+            stopped_by,
+            screen: snapshot.screen,
+        }),
+        blocked: None,
+        actions: HumanActions::default(),
+        streamed_lines,
+        duration,
+    }
+}
+
+/// Leave a timed-out command running and track it as a task in the pane it
+/// runs in, with the same `@tb_task` tag `tb launch` puts on task panes.
+/// `@tb_marker` lets `tb check` and `tb done` find the run's own output and
+/// exit code among the human's other scrollback, and `@tb_pgid` lets them
+/// tell when the command was stopped before it could print one.
+fn detach_run(watch: &RunWatch) -> Result<String, String> {
+    let _scope_lock = acquire_lock(
+        &scope_lock_name(watch.tmux_target)?,
+        Some(LOCK_WAIT),
+        "window",
+    )?;
+
+    let task_panes = list_panes_with_task_ids(watch.tmux_target)?;
+    let task_count = task_panes
+        .iter()
+        .filter(|(_, task_id)| !task_id.is_empty())
+        .count();
+    if task_count >= 6 {
+        return Err("too many background tasks (max 6).".to_string());
+    }
+
+    let task_id = next_task_id(&task_panes)?;
+    let pane_id = split_target(watch.tmux_target)?;
+
+    let mut options = vec![("@tb_marker", watch.marker_id.to_string())];
+    if let OutputSource::PipeLog(log_path) = watch.source {
+        options.push(("@tb_log", log_path.display().to_string()));
+    }
+    if let Some(pgid) = job_pgid(watch) {
+        options.push(("@tb_pgid", pgid.to_string()));
+    }
+    options.push(("@tb_task", task_id.clone()));
+    for (option, value) in options {
+        let _ = Command::new("tmux")
+            .args(["set-option", "-p", "-t", &pane_id, option, &value])
+            .status();
+    }
+
+    Ok(task_id)
+}
+
+/// What a timed-out command had printed, and the screen the human saw, both
+/// taken before the interrupt changes them.
+struct TimeoutSnapshot {
+    output: String,
+    screen: String,
+}
+
+fn take_timeout_snapshot(watch: &RunWatch) -> TimeoutSnapshot {
+    let output = watch
+        .source
+        .read(watch.tmux_target, false, watch.ansi.styled())
+        .map(|captured| {
+            let output = extract_partial_output(&captured.content, &watch.start_marker);
+            split_stderr_output(&output, &watch.stderr_marker).0
+        })
+        .unwrap_or_default();
+    let screen = capture_visible_screen(watch.tmux_target);
+
+    TimeoutSnapshot { output, screen }
+}
+
+/// The part of the pane the human can see right now, without scrollback.
+fn capture_visible_screen(pane_target: &str) -> String {
+    Command::new("tmux")
+        .args(["capture-pane", "-p", "-t", pane_target])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// Print what the command managed to output before it timed out, the same
+/// way a finished command's output is printed.
+fn print_partial_output(
+    output: &str,
+    streamed_lines: usize,
+    watch: &RunWatch,
+    saved_run: Option<&str>,
+) {
+    let (first, last) = watch.output_window;
+    if watch.stream {
+        finish_stream(output, streamed_lines, first, last, saved_run);
+    } else {
+        print_output(output, first, last, saved_run);
+    }
+}
+
+fn print_screen_snapshot(heading: &str, screen: &str) {
+    if !screen.is_empty() {
+        eprintln!();
+        eprintln!("{}", heading);
+        eprintln!("{}", screen);
+    }
+}
+
+/// Escalate through the kill sequence until the command stops, and return
+/// the signal that did it.
+///
+/// The command's process group is the one in the pane's foreground when the
+/// timeout fires. A step has worked once the end marker shows up or that
+/// group has no processes left; interrupting a wrapped command usually skips
+/// the end marker, and can leave part of the job running in the background.
+/// Without `/proc` the group is unknown, so fall back to waiting for the
+/// pane's foreground to return to what it was while the shell sat idle.
+/// The idle foreground is the human's shell, so it is never signalled.
+fn kill_running_command(watch: &RunWatch) -> Option<KillSignal> {
+    let tmux_target = watch.tmux_target;
+    let poll_interval = std::time::Duration::from_millis(100);
+    let job_pgid = job_pgid(watch);
+
+    let stopped = || {
+        let finished = watch
+            .source
+            .read(tmux_target, false, false)
+            .map(|captured| find_exit_code(&captured.content, &watch.end_marker_prefix).is_some())
+            .unwrap_or(false);
+        if finished {
+            return true;
+        }
+        match (job_pgid, &watch.idle_foreground) {
+            (Some(pgid), _) => !send_signal(pgid, "0"),
+            (None, Some(idle)) => pane_foreground(tmux_target).ok().as_ref() == Some(idle),
+            (None, None) => false,
+        }
+    };
+
+    for step in &watch.kill_sequence.0 {
+        match (step.signal.key(), job_pgid) {
+            (Some(key), _) => {
+                let _ = Command::new("tmux")
+                    .args(["send-keys", "-t", tmux_target, key])
+                    .status();
+            }
+            (None, Some(pgid)) => {
+                send_signal(pgid, step.signal.name());
+            }
+            (None, None) => {}
+        }
+
+        let deadline = std::time::Instant::now() + step.wait;
+        loop {
+            if stopped() {
+                return Some(step.signal);
+            }
+            if std::time::Instant::now() >= deadline {
+                break;
+            }
+            std::thread::sleep(poll_interval);
         }
     }
 
-    match (start_idx, end_idx) {
-        (Some(s), Some(e)) if s < e => lines[s..e].join("\n"),
-        _ => String::new(),
-    }
+    None
 }
 
-/// Print output with truncation if needed
-fn print_output(output: &str, first: usize, last: usize) {
-    let lines: Vec<&str> = output.lines().collect();
-    let total = lines.len();
+/// The process group of the command in the pane's foreground, unless that
+/// is still the human's idle shell.
+fn job_pgid(watch: &RunWatch) -> Option<i32> {
+    let idle_pgid = watch.idle_foreground.as_ref().and_then(|idle| idle.pgid);
+    pane_foreground(watch.tmux_target)
+        .ok()
+        .and_then(|foreground| foreground.pgid)
+        .filter(|&pgid| Some(pgid) != idle_pgid)
+}
 
-    if total <= first + last {
-        // No truncation needed
-        println!("{}", output);
-    } else {
-        // Print first N lines
-        for line in lines.iter().take(first) {
-            println!("{}", line);
-        }
+/// Send `signal` to every process in group `pgid`. Signal `0` only checks
+/// that the group still has a process. Returns whether `kill` succeeded.
+fn send_signal(pgid: i32, signal: &str) -> bool {
+    Command::new("kill")
+        .args(["-s", signal, "--", &format!("-{}", pgid)])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum KillSignal {
+    Int,
+    Quit,
+    Term,
+    Hup,
+    Kill,
+}
 
-        let truncated = total - first - last;
-        println!("\n... ({} lines truncated) ...\n", truncated);
+impl KillSignal {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Int => "INT",
+            Self::Quit => "QUIT",
+            Self::Term => "TERM",
+            Self::Hup => "HUP",
+            Self::Kill => "KILL",
+        }
+    }
 
-        // Print last N lines
-        for line in lines.iter().skip(total - last) {
-            println!("{}", line);
+    /// The key that makes the terminal send this signal, if there is one.
+    fn key(self) -> Option<&'static str> {
+        match self {
+            Self::Int => Some("C-c"),
+            Self::Quit => Some("C-\\"),
+            Self::Term | Self::Hup | Self::Kill => None,
         }
     }
 }
 
-/// Kill running command in pane with SIGINT, then SIGQUIT
-fn kill_running_command(tmux_target: &str) {
-    // Send Ctrl+C (SIGINT)
-    let _ = Command::new("tmux")
-        .args(["send-keys", "-t", tmux_target, "C-c"])
-        .status();
+#[derive(Clone, Debug)]
+struct KillStep {
+    signal: KillSignal,
+    wait: std::time::Duration,
+}
 
-    std::thread::sleep(std::time::Duration::from_secs(3));
+/// The `--kill-sequence` steps, tried in order.
+#[derive(Clone, Debug)]
+struct KillSequence(Vec<KillStep>);
+
+/// Parse `INT:3,QUIT:3,TERM:5,KILL`. A step without a wait gets 2 seconds.
+fn parse_kill_sequence(spec: &str) -> Result<KillSequence, String> {
+    let steps = spec
+        .split(',')
+        .map(|step| {
+            let (name, wait) = step.trim().split_once(':').unwrap_or((step.trim(), "2"));
+            let name = name.to_ascii_uppercase();
+            let signal = match name.strip_prefix("SIG").unwrap_or(&name) {
+                "INT" => KillSignal::Int,
+                "QUIT" => KillSignal::Quit,
+                "TERM" => KillSignal::Term,
+                "HUP" => KillSignal::Hup,
+                "KILL" => KillSignal::Kill,
+                other => {
+                    return Err(format!(
+                        "unknown signal '{}' (use INT, QUIT, TERM, HUP or KILL)",
+                        other
+                    ));
+                }
+            };
+            let wait = wait
+                .parse::<u64>()
+                .map_err(|_| format!("invalid wait '{}' for {} (use whole seconds)", wait, name))?;
+            Ok(KillStep {
+                signal,
+                wait: std::time::Duration::from_secs(wait),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
 
-    // Send Ctrl+\ (SIGQUIT) as backup
-    let _ = Command::new("tmux")
-        .args(["send-keys", "-t", tmux_target, "C-\\"])
-        .status();
+    Ok(KillSequence(steps))
 }
 
-fn cmd_launch(target: Option<String>, command: Vec<String>) -> Result<(), String> {
+fn cmd_launch(
+    target: Option<String>,
+    capture: CaptureMode,
+    no_wait: bool,
+    command: Vec<String>,
+) -> Result<(), String> {
     let tmux_target = resolve_tmux_target(target)?;
 
+    // Hold the window's lock from reading the task list until the new pane
+    // is tagged, so concurrent launches never claim the same task ID.
+    let lock_wait = (!no_wait).then_some(LOCK_WAIT);
+    let _scope_lock = acquire_lock(&scope_lock_name(&tmux_target)?, lock_wait, "window")?;
+
     // Count existing task panes to get next task ID
     let task_panes = list_panes_with_task_ids(&tmux_target)?;
     let task_count = task_panes
@@ -937,6 +4169,21 @@ fn cmd_launch(target: Option<String>, command: Vec<String>) -> Result<(), String
 
     let pane_target = String::from_utf8_lossy(&status.stdout).trim().to_string();
 
+    // Record before the command starts so the log holds its complete output
+    if capture == CaptureMode::Pipe {
+        let log_path = start_pipe_log(&pane_target, &format!("task-{}", random_marker_id()), true)?;
+        let _ = Command::new("tmux")
+            .args([
+                "set-option",
+                "-p",
+                "-t",
+                &pane_target,
+                "@tb_log",
+                &log_path.display().to_string(),
+            ])
+            .status();
+    }
+
     // Build the command to run in the task pane
     let cmd_str = shell_command_text(&command);
 
@@ -965,6 +4212,8 @@ fn cmd_launch(target: Option<String>, command: Vec<String>) -> Result<(), String
     Ok(())
 }
 
+/// The lowest task ID not in use. The human's pane counts while a detached
+/// `tb run` has it tagged, so a launch never takes its ID.
 fn next_task_id(task_panes: &[(String, String)]) -> Result<String, String> {
     let used_ids: HashSet<usize> = task_panes
         .iter()
@@ -983,9 +4232,11 @@ fn next_task_id(task_panes: &[(String, String)]) -> Result<String, String> {
 fn cmd_check(
     task: Option<String>,
     target: Option<String>,
-    first: usize,
-    last: usize,
+    output_window: (usize, usize),
+    capture: Option<CaptureMode>,
+    ansi: AnsiMode,
 ) -> Result<(), String> {
+    let (first, last) = output_window;
     let tmux_target = resolve_tmux_target(target)?;
 
     let (pane_id, task) = match task {
@@ -993,28 +4244,129 @@ fn cmd_check(
         None => (tmux_target.clone(), None),
     };
 
-    // Capture pane content
-    let output = capture_pane_scrollback(&pane_id)?;
+    let pane_content = read_pane_content(&pane_id, task.as_deref(), capture, ansi.styled())?;
 
-    if !output.status.success() {
-        return match task {
-            Some(task) => Err(format!("Task {} not found or pane inaccessible.", task)),
-            None => Err("Main pane not found or pane inaccessible.".to_string()),
-        };
+    if let Some(task) = task.as_deref()
+        && let Some(marker_id) = pane_option(&pane_id, "@tb_marker")
+    {
+        report_detached_run(
+            task,
+            &tmux_target,
+            &pane_id,
+            &marker_id,
+            &pane_content,
+            output_window,
+            ansi,
+        );
+        return Ok(());
     }
 
-    let pane_content = String::from_utf8_lossy(&output.stdout);
-
     // Print the pane content (with truncation)
-    print_output(&pane_content, first, last);
+    ansi.wrap(|| print_output(&ansi.render(&pane_content), first, last, None));
+    HumanActions::find(&pane_content).print();
 
     if let Some(task) = task.as_deref() {
-        report_task_check_status(task, &tmux_target, &pane_content);
+        report_task_check_status(task, &tmux_target, &strip_ansi(&pane_content));
     }
 
     Ok(())
 }
 
+/// Read what `tb check` shows for a pane: its pipe-pane log when it has one
+/// (unless `capture` says otherwise), else its scrollback.
+fn read_pane_content(
+    pane_id: &str,
+    task: Option<&str>,
+    capture: Option<CaptureMode>,
+    styled: bool,
+) -> Result<String, String> {
+    let log_path = pane_option(pane_id, "@tb_log");
+    match (capture, log_path) {
+        (Some(CaptureMode::Pipe) | None, Some(log_path)) => {
+            let bytes = std::fs::read(&log_path)
+                .map_err(|e| format!("Failed to read task log {}: {}", log_path, e))?;
+            Ok(render_terminal_log(&bytes, styled).content)
+        }
+        (Some(CaptureMode::Pipe), None) => Err(
+            "This pane has no pipe-pane log.\n\nLaunch the task with: tb launch --capture pipe --target TARGET -- <command>"
+                .to_string(),
+        ),
+        (Some(CaptureMode::Scrollback) | None, _) => {
+            let output = capture_pane_scrollback(pane_id, styled)?;
+
+            if !output.status.success() {
+                return match task {
+                    Some(task) => Err(format!("Task {} not found or pane inaccessible.", task)),
+                    None => Err("Main pane not found or pane inaccessible.".to_string()),
+                };
+            }
+
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+    }
+}
+
+/// Check on a `tb run` detached on timeout: show only that run's output,
+/// and its exit code once the end marker is there.
+fn report_detached_run(
+    task: &str,
+    tmux_target: &str,
+    pane_id: &str,
+    marker_id: &str,
+    pane_content: &str,
+    output_window: (usize, usize),
+    ansi: AnsiMode,
+) {
+    let (first, last) = output_window;
+    let start_marker = format!("___START_{}___", marker_id);
+    let end_marker_prefix = format!("___END_{}_", marker_id);
+    let exit_code = find_exit_code(pane_content, &end_marker_prefix);
+    let output = match exit_code {
+        Some(_) => extract_output(pane_content, &start_marker, &end_marker_prefix),
+        None => extract_partial_output(pane_content, &start_marker),
+    };
+    let (output, stderr) = split_stderr_output(&output, &format!("___STDERR_{}___", marker_id));
+
+    // Once the run has finished, its sign-in links are spent.
+    let actions = match exit_code {
+        Some(_) => HumanActions::default(),
+        None => HumanActions::find(&output),
+    };
+    // Only a finished run's output is complete enough to save.
+    let saved_run = exit_code.and_then(|_| save_run_output(marker_id, &output, first, last));
+    let output = ansi.render(&output);
+    ansi.wrap(|| print_output(&output, first, last, saved_run));
+    if let Some(stderr) = stderr.filter(|text| !text.is_empty()) {
+        eprint!(
+            "{}",
+            truncate_output(&ansi.render(&stderr), first, last, None)
+        );
+    }
+    actions.print();
+
+    match exit_code {
+        Some(code) => {
+            println!();
+            println!("Task {} finished with exit code {}.", task, code);
+        }
+        None if detached_run_stopped(pane_id) => {
+            println!();
+            println!(
+                "Task {} stopped without an exit code; it was probably interrupted.",
+                task
+            );
+        }
+        None => return,
+    }
+    // The run is over, so the human's pane needn't be recorded any more.
+    // The log stays until `tb done` for checks in the meantime.
+    if pane_option(pane_id, "@tb_log").is_some() {
+        stop_recording(pane_id);
+    }
+    restore_detached_pending_input(pane_id);
+    println!("Release it with: tb done --target {} {}", tmux_target, task);
+}
+
 fn report_task_check_status(task: &str, tmux_target: &str, pane_content: &str) {
     if is_process_running(pane_content) {
         return;
@@ -1084,11 +4436,453 @@ fn find_task_exit_code(pane_content: &str) -> Option<i32> {
     None
 }
 
-fn cmd_done(task: String, target: Option<String>) -> Result<(), String> {
+#[derive(Clone, Copy, Debug)]
+struct LineRange {
+    start: usize,
+    end: Option<usize>,
+}
+
+/// Parse `START:END` into 1-based line numbers. Either end may be empty.
+fn parse_line_range(text: &str) -> Result<LineRange, String> {
+    let invalid = || format!("invalid range '{}' (use START:END, e.g. 200:400)", text);
+    let (start, end) = text.split_once(':').ok_or_else(invalid)?;
+    let start = match start.trim() {
+        "" => 1,
+        start => start.parse::<usize>().map_err(|_| invalid())?,
+    };
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse::<usize>().map_err(|_| invalid())?),
+    };
+    if start == 0 || end.is_some_and(|end| end < start) {
+        return Err(invalid());
+    }
+    Ok(LineRange { start, end })
+}
+
+/// What `tb wait` polls the pane for.
+enum WaitCondition {
+    Appears(regex::Regex),
+    Gone(regex::Regex),
+    Quiet(std::time::Duration),
+}
+
+impl WaitCondition {
+    fn describe(&self) -> String {
+        match self {
+            WaitCondition::Appears(regex) => format!("'{}' to appear", regex),
+            WaitCondition::Gone(regex) => format!("'{}' to disappear", regex),
+            WaitCondition::Quiet(period) => {
+                format!("output to stay quiet for {}s", period.as_secs())
+            }
+        }
+    }
+}
+
+/// Where new output starts in a pane that is read over and over.
+///
+/// Everything before the last line there at the start is old. That last line
+/// stays in, since a program waiting for input has its prompt there. The
+/// lines just before it locate the spot again after tmux drops old history
+/// from the top; if they are gone (the pane was cleared), all of it is new.
+struct OutputMark {
+    index: usize,
+    anchor: Vec<String>,
+}
+
+impl OutputMark {
+    fn new(lines: &[&str]) -> OutputMark {
+        let index = lines.len().saturating_sub(1);
+        OutputMark {
+            index,
+            anchor: lines[index.saturating_sub(3)..index]
+                .iter()
+                .map(|line| line.to_string())
+                .collect(),
+        }
+    }
+
+    /// The index in `lines` where output after the mark starts.
+    fn find(&self, lines: &[&str]) -> usize {
+        let size = self.anchor.len();
+        if size == 0 {
+            return 0;
+        }
+        (size..=self.index.min(lines.len()))
+            .rev()
+            .find(|&end| lines[end - size..end] == self.anchor[..])
+            .unwrap_or(0)
+    }
+}
+
+struct WaitOptions {
+    task: Option<String>,
+    target: Option<String>,
+    pattern: Option<String>,
+    gone: bool,
+    quiet: Option<u64>,
+    timeout: u64,
+    context: usize,
+    existing: bool,
+}
+
+fn cmd_wait(options: WaitOptions) -> Result<(), String> {
+    let WaitOptions {
+        task,
+        target,
+        pattern,
+        gone,
+        quiet,
+        timeout,
+        context,
+        existing,
+    } = options;
+
+    let condition = match (pattern, quiet) {
+        (Some(pattern), _) => {
+            let regex = regex::Regex::new(&pattern)
+                .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+            if gone {
+                WaitCondition::Gone(regex)
+            } else {
+                WaitCondition::Appears(regex)
+            }
+        }
+        (None, Some(seconds)) => WaitCondition::Quiet(std::time::Duration::from_secs(seconds)),
+        (None, None) => {
+            return Err(
+                "Nothing to wait for.\n\nUse --pattern REGEX, or --quiet SECONDS to wait for the output to settle."
+                    .to_string(),
+            );
+        }
+    };
+
+    let tmux_target = resolve_tmux_target(target)?;
+    let pane_id = match task.as_deref() {
+        Some(task) => find_task_pane(&tmux_target, task)?,
+        None => tmux_target.clone(),
+    };
+
+    let start_time = std::time::Instant::now();
+    let deadline = start_time + std::time::Duration::from_secs(timeout);
+    let poll_interval = std::time::Duration::from_millis(100);
+    let mut last_content = String::new();
+    let mut last_change = start_time;
+    let mut mark = None;
+
+    loop {
+        let content = read_pane_content(&pane_id, task.as_deref(), None, false)?;
+        let lines: Vec<&str> = content.trim_end().lines().collect();
+        // A stale "Listening on :3000" from an earlier run must not count
+        // as appearing. Gone still looks at everything the pane shows.
+        let from = match existing {
+            true => 0,
+            false => mark
+                .get_or_insert_with(|| OutputMark::new(&lines))
+                .find(&lines),
+        };
+
+        let shown = match &condition {
+            WaitCondition::Appears(regex) => lines[from..]
+                .iter()
+                .rposition(|line| regex.is_match(line))
+                .map(|index| from + index)
+                .map(|index| index.saturating_sub(context)..(index + context + 1).min(lines.len())),
+            WaitCondition::Gone(regex) => (!lines.iter().any(|line| regex.is_match(line)))
+                .then(|| lines.len().saturating_sub(context)..lines.len()),
+            WaitCondition::Quiet(period) => {
+                if content != last_content {
+                    last_change = std::time::Instant::now();
+                }
+                (last_change.elapsed() >= *period)
+                    .then(|| lines.len().saturating_sub(context)..lines.len())
+            }
+        };
+
+        if let Some(range) = shown {
+            for line in &lines[range] {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+
+        if std::time::Instant::now() >= deadline {
+            for line in &lines[lines.len().saturating_sub(context)..] {
+                println!("{}", line);
+            }
+            eprintln!(
+                "Timed out after {}s waiting for {}.",
+                timeout,
+                condition.describe()
+            );
+            std::process::exit(124);
+        }
+
+        last_content = content;
+        std::thread::sleep(poll_interval);
+    }
+}
+
+/// One step of a `tb expect` dialog, with the text it was written as so
+/// failures can say which step it was.
+#[derive(Clone)]
+struct ExpectStep {
+    text: String,
+    action: ExpectAction,
+}
+
+#[derive(Clone)]
+enum ExpectAction {
+    Expect(regex::Regex),
+    Send(String),
+    Timeout(u64),
+    Capture,
+}
+
+fn parse_expect_step(text: &str) -> Result<ExpectStep, String> {
+    let invalid = || {
+        format!(
+            "invalid step '{}' (use expect:/REGEX/, send:TEXT, timeout:SECONDS or capture)",
+            text
+        )
+    };
+    let (kind, argument) = text.split_once(':').unwrap_or((text, ""));
+    let action = match kind {
+        "expect" => {
+            // Slashes around the regex are optional; `\/` inside them is a slash.
+            let pattern = argument
+                .strip_prefix('/')
+                .and_then(|rest| rest.strip_suffix('/'))
+                .unwrap_or(argument)
+                .replace("\\/", "/");
+            let regex = regex::Regex::new(&pattern)
+                .map_err(|e| format!("invalid pattern in step '{}': {}", text, e))?;
+            ExpectAction::Expect(regex)
+        }
+        "send" => ExpectAction::Send(unescape_send_text(argument).ok_or_else(invalid)?),
+        "timeout" => ExpectAction::Timeout(argument.parse().map_err(|_| invalid())?),
+        "capture" if argument.is_empty() => ExpectAction::Capture,
+        _ => return Err(invalid()),
+    };
+
+    Ok(ExpectStep {
+        text: text.to_string(),
+        action,
+    })
+}
+
+/// Decode `\n`, `\r`, `\t`, `\e` and `\\` in a send step.
+fn unescape_send_text(text: &str) -> Option<String> {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'e' => '\x1b',
+            '\\' => '\\',
+            _ => return None,
+        });
+    }
+    Some(unescaped)
+}
+
+/// Read a dialog script: one step per line, skipping blank lines and `#`
+/// comments.
+fn read_expect_script(path: &std::path::Path) -> Result<Vec<ExpectStep>, String> {
+    let script = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    script
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            parse_expect_step(line.trim_start())
+                .map_err(|e| format!("{} line {}: {}", path.display(), index + 1, e))
+        })
+        .collect()
+}
+
+/// Type text into a pane. Literal runs go through `send-keys -l`; newlines,
+/// tabs and escapes are sent as the keys they stand for.
+fn send_text(pane_id: &str, text: &str) -> Result<(), String> {
+    let send = |args: &[&str]| -> Result<(), String> {
+        let status = Command::new("tmux")
+            .args(["send-keys", "-t", pane_id])
+            .args(args)
+            .status()
+            .map_err(|e| format!("Failed to send keys to tmux: {}", e))?;
+        if !status.success() {
+            return Err("Failed to send keys to tmux.".to_string());
+        }
+        Ok(())
+    };
+
+    let mut literal = String::new();
+    for c in text.chars() {
+        let key = match c {
+            '\n' | '\r' => "Enter",
+            '\t' => "Tab",
+            '\x1b' => "Escape",
+            _ => {
+                literal.push(c);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            send(&["-l", "--", &literal])?;
+            literal.clear();
+        }
+        send(&[key])?;
+    }
+    if !literal.is_empty() {
+        send(&["-l", "--", &literal])?;
+    }
+    Ok(())
+}
+
+/// Run a dialog step by step.
+///
+/// Each expect only looks at output after the previous match, and after the
+/// last send, so a prompt that repeats is not matched twice.
+fn cmd_expect(
+    task: Option<String>,
+    target: Option<String>,
+    script: Option<std::path::PathBuf>,
+    steps: Vec<ExpectStep>,
+    timeout: u64,
+) -> Result<(), String> {
+    let steps = match script {
+        Some(path) => read_expect_script(&path)?,
+        None => steps,
+    };
+    if steps.is_empty() {
+        return Err(
+            "No steps to run.\n\nUse --script FILE, or --step STEP for each step.".to_string(),
+        );
+    }
+
+    let tmux_target = resolve_tmux_target(target)?;
+    let pane_id = match task.as_deref() {
+        Some(task) => find_task_pane(&tmux_target, task)?,
+        None => tmux_target.clone(),
+    };
+
+    // Queue behind any other tb command typing into this pane.
+    let _pane_lock = acquire_lock(&pane_lock_name(&pane_id)?, Some(LOCK_WAIT), "pane")?;
+    leave_pane_mode(&pane_id)?;
+
+    let poll_interval = std::time::Duration::from_millis(100);
+    let read_output = || {
+        read_pane_content(&pane_id, task.as_deref(), None, false)
+            .map(|content| content.trim_end().to_string())
+    };
+    let mut step_timeout = timeout;
+    // Start at the last line, where a waiting program shows its prompt, so
+    // a prompt left over from an earlier run is not answered.
+    let mut search_from = read_output()?.rfind('\n').map_or(0, |i| i + 1);
+
+    for (index, step) in steps.iter().enumerate() {
+        match &step.action {
+            ExpectAction::Expect(regex) => {
+                let deadline =
+                    std::time::Instant::now() + std::time::Duration::from_secs(step_timeout);
+                loop {
+                    let output = read_output()?;
+                    // Start over if the pane was cleared under us.
+                    let start = if output.is_char_boundary(search_from) {
+                        search_from
+                    } else {
+                        0
+                    };
+                    if let Some(found) = regex.find(&output[start..]) {
+                        let match_start = start + found.start();
+                        let line_start = output[..match_start].rfind('\n').map_or(0, |i| i + 1);
+                        let line_end = output[match_start..]
+                            .find('\n')
+                            .map_or(output.len(), |i| match_start + i);
+                        println!("{}", &output[line_start..line_end]);
+                        search_from = start + found.end();
+                        break;
+                    }
+                    if std::time::Instant::now() >= deadline {
+                        eprintln!(
+                            "Step {} ({}) timed out after {}s.",
+                            index + 1,
+                            step.text,
+                            step_timeout
+                        );
+                        let screen = capture_visible_screen(&pane_id);
+                        if !screen.is_empty() {
+                            eprintln!();
+                            eprintln!("Screen at that point:");
+                            eprintln!("{}", screen);
+                        }
+                        std::process::exit(124);
+                    }
+                    std::thread::sleep(poll_interval);
+                }
+            }
+            ExpectAction::Send(text) => {
+                search_from = read_output()?.len();
+                send_text(&pane_id, text)?;
+            }
+            ExpectAction::Timeout(seconds) => step_timeout = *seconds,
+            ExpectAction::Capture => println!("{}", capture_visible_screen(&pane_id)),
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_output(run_id: String, range: Option<LineRange>) -> Result<(), String> {
+    let not_found = || {
+        format!(
+            "No saved output for run '{}'.\n\ntb run only saves output it had to truncate; use the ID from its truncation banner.",
+            run_id
+        )
+    };
+    if run_id.is_empty() || !run_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(not_found());
+    }
+    let path = run_output_path(&run_id).ok_or_else(not_found)?;
+    let output = std::fs::read_to_string(&path).map_err(|_| not_found())?;
+
+    let lines: Vec<&str> = output.lines().collect();
+    let LineRange { start, end } = range.unwrap_or(LineRange {
+        start: 1,
+        end: None,
+    });
+    if start > lines.len() {
+        return Err(format!(
+            "Run '{}' only has {} lines of output.",
+            run_id,
+            lines.len()
+        ));
+    }
+    let end = end.unwrap_or(lines.len()).min(lines.len());
+    for line in &lines[start - 1..end] {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn cmd_done(task: String, target: Option<String>, force: bool) -> Result<(), String> {
     let tmux_target = resolve_tmux_target(target)?;
 
     // Find the pane with the matching task title
     let pane_id = find_task_pane(&tmux_target, &task)?;
+    let log_path = pane_option(&pane_id, "@tb_log");
+
+    if let Some(marker_id) = pane_option(&pane_id, "@tb_marker") {
+        return release_detached_run(&task, &tmux_target, &pane_id, &marker_id, log_path, force);
+    }
 
     // Kill the pane
     let status = Command::new("tmux")
@@ -1100,11 +4894,90 @@ fn cmd_done(task: String, target: Option<String>) -> Result<(), String> {
         return Err(format!("Failed to close task {}.", task));
     }
 
+    if let Some(log_path) = log_path {
+        let _ = std::fs::remove_file(log_path);
+    }
+
     println!("Closed task {}.", task);
 
     Ok(())
 }
 
+/// Finish with a `tb run` detached on timeout. It runs in the human's own
+/// pane, so untag the pane instead of closing it, and only once the command
+/// has finished or stopped (unless `force`).
+fn release_detached_run(
+    task: &str,
+    tmux_target: &str,
+    pane_id: &str,
+    marker_id: &str,
+    log_path: Option<String>,
+    force: bool,
+) -> Result<(), String> {
+    let pane_content = match &log_path {
+        Some(log_path) => {
+            render_terminal_log(&std::fs::read(log_path).unwrap_or_default(), false).content
+        }
+        None => {
+            String::from_utf8_lossy(&capture_pane_scrollback(pane_id, false)?.stdout).into_owned()
+        }
+    };
+
+    let finished = find_exit_code(&pane_content, &format!("___END_{}_", marker_id)).is_some();
+    if !force && !finished && !detached_run_stopped(pane_id) {
+        return Err(format!(
+            "Task {} is still running in the user's pane.\n\nCheck it with: tb check --target {} {}\nOr ask the user to stop it. To untag the pane anyway: tb done --force --target {} {}",
+            task, tmux_target, task, tmux_target, task
+        ));
+    }
+
+    if let Some(log_path) = log_path {
+        stop_pipe_log(pane_id, std::path::Path::new(&log_path));
+    }
+    remove_side_files(marker_id);
+    // Never type the human's input into a command that is still running.
+    let pending_input = if finished || detached_run_stopped(pane_id) {
+        restore_detached_pending_input(pane_id)
+    } else {
+        pane_option(pane_id, "@tb_pending")
+    };
+    if let Some(pending_input) = pending_input {
+        eprintln!(
+            "Could not restore the user's half-typed command line: {}",
+            pending_input
+        );
+    }
+    for option in [
+        "@tb_task",
+        "@tb_marker",
+        "@tb_log",
+        "@tb_pgid",
+        "@tb_pending",
+    ] {
+        let _ = Command::new("tmux")
+            .args(["set-option", "-p", "-u", "-t", pane_id, option])
+            .status();
+    }
+
+    println!("Released task {}; its pane stays open.", task);
+
+    Ok(())
+}
+
+/// Whether a detached run's command has stopped without printing its end
+/// marker, as after the human presses Ctrl-C: the process group it had when
+/// it was detached is gone (if `/proc` told us which), and the pane's shell
+/// is back at a bare prompt. A job started after it, or a builtin such as
+/// `read` that runs in the shell itself, does not count.
+fn detached_run_stopped(pane_id: &str) -> bool {
+    let job_gone = pane_option(pane_id, "@tb_pgid")
+        .and_then(|pgid| pgid.parse::<i32>().ok())
+        .is_none_or(|pgid| !send_signal(pgid, "0"));
+    job_gone
+        && pane_foreground(pane_id).is_ok_and(|foreground| foreground.is_idle_shell())
+        && read_prompt_line(pane_id).is_ok_and(|line| line.is_bare_prompt())
+}
+
 /// Find pane ID for a task by its @tb_task option
 fn list_panes_with_task_ids(tmux_target: &str) -> Result<Vec<(String, String)>, String> {
     let scope = pane_list_scope(tmux_target)?;
@@ -1145,6 +5018,23 @@ fn pane_list_scope(tmux_target: &str) -> Result<String, String> {
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
+/// Read a user option (e.g. `@tb_log`) from a pane; `None` when unset.
+fn pane_option(pane_id: &str, option: &str) -> Option<String> {
+    let output = Command::new("tmux")
+        .args([
+            "display-message",
+            "-p",
+            "-t",
+            pane_id,
+            &format!("#{{{}}}", option),
+        ])
+        .output()
+        .ok()?;
+
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
 fn find_task_pane(tmux_target: &str, task: &str) -> Result<String, String> {
     for (pane_id, task_id) in list_panes_with_task_ids(tmux_target)? {
         if task_id == task {
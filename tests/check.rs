@@ -278,3 +278,169 @@ mod check_errors {
             .stderr(predicate::str::contains("No target specified"));
     }
 }
+
+mod check_pipe_capture {
+    use super::*;
+
+    #[test]
+    fn reads_task_log_recorded_by_launch() {
+        let session = TestSession::new();
+        let long_line = "y".repeat(300);
+
+        let output = session
+            .tb_command()
+            .args([
+                "launch",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--",
+                "printf",
+                "%s\\n",
+                &long_line,
+            ])
+            .output()
+            .expect("Failed to run tb launch");
+        assert!(output.status.success());
+
+        // The task pane is only 5 rows tall, so scrollback would wrap this
+        // line many times; the pipe-pane log keeps it whole.
+        let stdout = session.wait_for_check_output("t1", |stdout| stdout.contains("tb done"));
+        assert!(
+            stdout.lines().any(|line| line == long_line),
+            "expected the long line intact\n{}",
+            stdout
+        );
+    }
+
+    #[test]
+    fn task_log_is_private_and_goes_with_its_pane() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let session = TestSession::new();
+        session
+            .tb_command()
+            .args(["launch", "--target", session.target(), "--capture", "pipe"])
+            .args(["--", "sleep", "60"])
+            .assert()
+            .success();
+
+        let panes = StdCommand::new("tmux")
+            .args(["list-panes", "-t", &session.tmux_name()])
+            .args(["-F", "#{pane_id} #{@tb_log}"])
+            .output()
+            .expect("Failed to list panes");
+        let panes = String::from_utf8_lossy(&panes.stdout).into_owned();
+        let (pane_id, log_path) = panes
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, log)| !log.is_empty())
+            .expect("task pane should have a log");
+        let log_path = std::path::Path::new(log_path);
+        let mode = std::fs::metadata(log_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // The human closes the pane instead of running `tb done`.
+        StdCommand::new("tmux")
+            .args(["kill-pane", "-t", pane_id])
+            .status()
+            .expect("Failed to close task pane");
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while log_path.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(!log_path.exists(), "task log left behind");
+    }
+
+    #[test]
+    fn fails_when_task_has_no_log() {
+        let session = TestSession::new();
+        let task_id = session.launch_task(&["sleep", "60"]);
+
+        session
+            .tb_command()
+            .args([
+                "check",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                &task_id,
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("no pipe-pane log"))
+            .stderr(predicate::str::contains("--capture pipe"));
+    }
+}
+
+mod check_ansi {
+    use super::*;
+
+    #[test]
+    fn keeps_or_strips_main_pane_colors() {
+        let session = TestSession::new();
+
+        session.send_main_pane_command("printf '\\033[32mgreen\\033[0m text\\n'");
+        session.wait_for_main_check_output(|stdout| stdout.contains("green text"));
+
+        let check = |ansi: &str| {
+            let output = session
+                .tb_command()
+                .args(["check", "--target", session.target(), "--ansi", ansi])
+                .output()
+                .expect("Failed to run tb check");
+            assert!(output.status.success());
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+
+        assert!(check("strip").contains("\ngreen text\n"));
+        assert!(check("keep").contains("\x1b[32mgreen"));
+        assert!(check("html").contains("<span style=\"color:#00cd00\">green</span> text"));
+    }
+}
+
+mod check_wrapped_lines {
+    use super::*;
+
+    #[test]
+    fn joins_lines_the_pane_wrapped() {
+        let session = TestSession::new();
+        let status = StdCommand::new("tmux")
+            .args(["resize-window", "-t", &session.tmux_name(), "-x", "30"])
+            .status()
+            .expect("Failed to resize window");
+        assert!(status.success());
+
+        let line: String = (0..20).map(|n| format!("item-{n:02};")).collect();
+        session.send_main_pane_command(&format!("printf '%s\\n' '{line}'"));
+        let stdout = session.wait_for_main_check_output(|stdout| stdout.contains("item-19;"));
+        assert!(
+            stdout.lines().any(|captured| captured == line),
+            "expected the long line intact\n{stdout}"
+        );
+    }
+}
+
+mod check_human_actions {
+    use super::*;
+
+    #[test]
+    fn lists_sign_in_link_and_code_from_task() {
+        let session = TestSession::new();
+        let task_id = session.launch_task(&[
+            "sh",
+            "-c",
+            "echo 'Open https://github.com/login/device and enter code WDJB-MJHT'; sleep 30",
+        ]);
+
+        let stdout = session.wait_for_check_output(&task_id, |stdout| {
+            stdout.contains("Action needed by human:")
+        });
+        assert!(
+            stdout.contains("  Open https://github.com/login/device\n  Enter code WDJB-MJHT\n"),
+            "check output: {stdout}"
+        );
+    }
+}
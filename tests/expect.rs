@@ -0,0 +1,177 @@
+//! End-to-end black-box tests for `tb expect`
+//!
+//! These tests verify running scripted send/expect dialogs against a pane.
+
+mod common;
+
+use common::TestSession;
+use predicates::prelude::*;
+
+/// Start a two-question wizard in the main pane. The prompts are assembled
+/// with printf so the typed command line does not already match them.
+fn start_wizard(session: &TestSession) {
+    session.send_main_pane_command(
+        "printf 'Continue? [%s] ' y/N; read answer; printf 'Project %s: ' name; read name; echo \"created $name ($answer)\"",
+    );
+}
+
+mod expect_dialogs {
+    use super::*;
+
+    #[test]
+    fn inline_steps_drive_the_dialog() {
+        let session = TestSession::new();
+        start_wizard(&session);
+
+        session
+            .tb_command()
+            .args([
+                "expect",
+                "--target",
+                session.target(),
+                "--step",
+                r"expect:/\[y\/N\]/",
+                "--step",
+                r"send:y\n",
+                "--step",
+                "expect:/Project name:/",
+                "--step",
+                r"send:demo\n",
+                "--step",
+                r"expect:/created demo \(y\)/",
+                "--step",
+                "capture",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Continue? [y/N]"))
+            .stdout(predicate::str::contains("created demo (y)"));
+    }
+
+    #[test]
+    fn sends_text_that_starts_with_a_dash() {
+        let session = TestSession::new();
+        start_wizard(&session);
+
+        session
+            .tb_command()
+            .args([
+                "expect",
+                "--target",
+                session.target(),
+                "--step",
+                r"expect:/\[y\/N\]/",
+                "--step",
+                r"send:-1\n",
+                "--step",
+                "expect:/Project name:/",
+                "--step",
+                r"send:-x\n",
+                "--step",
+                r"expect:/created -x \(-1\)/",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("created -x (-1)"));
+    }
+
+    #[test]
+    fn script_file_skips_comments_and_blank_lines() {
+        let session = TestSession::new();
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("wizard.tbx");
+        std::fs::write(
+            &script,
+            "# answer the wizard\nexpect:/y\\/N/\nsend:n\\n\n\nexpect:/Project name:/\nsend:other\\n\nexpect:/created other/\n",
+        )
+        .unwrap();
+        start_wizard(&session);
+
+        session
+            .tb_command()
+            .args(["expect", "--target", session.target(), "--script"])
+            .arg(&script)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("created other (n)"));
+    }
+}
+
+mod expect_failures {
+    use super::*;
+
+    #[test]
+    fn reports_the_step_that_timed_out_and_the_screen() {
+        let session = TestSession::new();
+        session.send_main_pane_command("echo visible-before-timeout");
+        session.wait_for_main_check_output(|stdout| stdout.contains("visible-before-timeout\n"));
+
+        session
+            .tb_command()
+            .args([
+                "expect",
+                "--target",
+                session.target(),
+                "--step",
+                "timeout:1",
+                "--step",
+                "expect:/never-shown/",
+            ])
+            .assert()
+            .code(124)
+            .stderr(predicate::str::contains(
+                "Step 2 (expect:/never-shown/) timed out after 1s.",
+            ))
+            .stderr(predicate::str::contains("Screen at that point:"))
+            .stderr(predicate::str::contains("visible-before-timeout"));
+    }
+
+    #[test]
+    fn ignores_a_prompt_left_by_an_earlier_command() {
+        let session = TestSession::new();
+        session.send_main_pane_command("echo 'Continue? [y/N]'; echo done");
+        session.wait_for_main_check_output(|stdout| stdout.contains("done\n"));
+
+        session
+            .tb_command()
+            .args([
+                "expect",
+                "--target",
+                session.target(),
+                "--step",
+                "timeout:1",
+                "--step",
+                r"expect:/\[y\/N\]/",
+            ])
+            .assert()
+            .code(124);
+    }
+
+    #[test]
+    fn rejects_unknown_steps() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args(["expect", "--target", session.target(), "--step", "bogus"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid step 'bogus'"));
+    }
+
+    #[test]
+    fn script_errors_name_the_line() {
+        let session = TestSession::new();
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("broken.tbx");
+        std::fs::write(&script, "expect:/ok/\nsend:bad\\q\n").unwrap();
+
+        session
+            .tb_command()
+            .args(["expect", "--target", session.target(), "--script"])
+            .arg(&script)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("broken.tbx line 2: invalid step"));
+    }
+}
@@ -131,3 +131,26 @@ mod info_shell_assessment {
             .stdout(predicate::str::contains("Foreground command").not());
     }
 }
+
+mod info_pane_modes {
+    use super::*;
+
+    #[test]
+    fn leaves_copy_mode_before_probing() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        let status = std::process::Command::new("tmux")
+            .args(["copy-mode", "-t", &session.tmux_name()])
+            .status()
+            .expect("Failed to enter copy-mode");
+        assert!(status.success());
+
+        session
+            .tb_command()
+            .args(["info", "--target", session.target()])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("bash"))
+            .stderr(predicate::str::contains("left tmux copy-mode"));
+    }
+}
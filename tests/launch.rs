@@ -198,6 +198,79 @@ mod launch_pane_splitting {
     }
 }
 
+mod launch_task_ids {
+    use super::*;
+
+    #[test]
+    fn concurrent_launches_get_distinct_task_ids() {
+        let session = TestSession::new();
+
+        let children: Vec<_> = (0..4)
+            .map(|_| {
+                StdCommand::new(assert_cmd::cargo::cargo_bin("tb"))
+                    .env("TB_TEST_MODE", "1")
+                    .env("TB_SESSION_PREFIX", session.session_prefix())
+                    .args(["launch", "--target", session.target(), "--", "sleep", "60"])
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .expect("Failed to spawn tb launch")
+            })
+            .collect();
+
+        let mut task_ids: Vec<String> = children
+            .into_iter()
+            .map(|child| {
+                let output = child
+                    .wait_with_output()
+                    .expect("Failed to wait for tb launch");
+                assert!(output.status.success(), "tb launch failed");
+                let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                stdout
+                    .split_whitespace()
+                    .nth(1)
+                    .expect("launch output should name the task")
+                    .to_string()
+            })
+            .collect();
+        task_ids.sort();
+
+        assert_eq!(task_ids, ["t1", "t2", "t3", "t4"]);
+    }
+}
+
+mod launch_pane_modes {
+    use super::*;
+
+    #[test]
+    fn leaves_target_pane_in_copy_mode() {
+        let session = TestSession::new();
+        let status = StdCommand::new("tmux")
+            .args(["copy-mode", "-t", &session.tmux_name()])
+            .status()
+            .expect("Failed to enter copy-mode");
+        assert!(status.success());
+
+        session
+            .tb_command()
+            .args(["launch", "--target", session.target(), "--", "sleep", "60"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Task t1 started"));
+
+        let mode = StdCommand::new("tmux")
+            .args([
+                "display-message",
+                "-p",
+                "-t",
+                &session.tmux_name(),
+                "#{pane_mode}",
+            ])
+            .output()
+            .expect("Failed to read pane mode");
+        assert_eq!(String::from_utf8_lossy(&mode.stdout).trim(), "copy-mode");
+    }
+}
+
 mod launch_session_resolution {
     use super::*;
 
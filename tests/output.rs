@@ -0,0 +1,195 @@
+//! End-to-end black-box tests for `tb output`
+//!
+//! These tests verify reading back the full output of a truncated `tb run`.
+
+mod common;
+
+use common::TestSession;
+use predicates::prelude::*;
+
+/// Run `seq 1 <count>` with a 2+2 line window and return its run ID.
+fn run_truncated(session: &TestSession, state_home: &std::path::Path, count: &str) -> String {
+    let assert = session
+        .tb_command()
+        .env("XDG_STATE_HOME", state_home)
+        .args([
+            "run",
+            "--target",
+            session.target(),
+            "--first",
+            "2",
+            "--last",
+            "2",
+            "--",
+            "seq",
+            "1",
+            count,
+        ])
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Page through it with: tb output "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+        .unwrap_or_else(|| panic!("no run ID in output:\n{stdout}"))
+}
+
+mod output_saved_runs {
+    use super::*;
+
+    #[test]
+    fn truncation_banner_points_at_saved_output() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+
+        let assert = session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--first",
+                "2",
+                "--last",
+                "2",
+                "--",
+                "seq",
+                "1",
+                "10",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::starts_with("1\n2\n"))
+            .stdout(predicate::str::contains("(6 lines truncated)"))
+            .stdout(predicate::str::ends_with("9\n10\n"));
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+
+        let runs_dir = state_home.path().join("tb").join("runs");
+        assert!(
+            stdout.contains(&format!("Full output saved to {}", runs_dir.display())),
+            "stdout: {stdout}"
+        );
+        assert!(stdout.contains("--range 3:8"), "stdout: {stdout}");
+    }
+
+    #[test]
+    fn prints_full_output_or_a_range() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+        let run_id = run_truncated(&session, state_home.path(), "10");
+
+        let expected: String = (1..=10).map(|n| format!("{n}\n")).collect();
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", &run_id])
+            .assert()
+            .success()
+            .stdout(predicate::eq(expected));
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", &run_id, "--range", "3:5"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("3\n4\n5\n"));
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", &run_id, "--range", "9:"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("9\n10\n"));
+    }
+
+    #[test]
+    fn saves_plain_text_whatever_ansi_prints() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+
+        let assert = session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["run", "--target", session.target(), "--ansi", "html"])
+            .args(["--first", "1", "--last", "1", "--"])
+            .arg(r"printf '\033[31mred\033[0m\n'; seq 1 3")
+            .assert()
+            .success();
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+        let run_id = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("Page through it with: tb output "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap_or_else(|| panic!("no run ID in output:\n{stdout}"));
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", run_id])
+            .assert()
+            .success()
+            .stdout(predicate::eq("red\n1\n2\n3\n"));
+    }
+
+    #[test]
+    fn keeps_only_the_most_recent_runs() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+        let runs_dir = state_home.path().join("tb").join("runs");
+        std::fs::create_dir_all(&runs_dir).unwrap();
+        for n in 0..60 {
+            std::fs::write(runs_dir.join(format!("old{n}.log")), "old\n").unwrap();
+        }
+
+        let run_id = run_truncated(&session, state_home.path(), "10");
+
+        assert_eq!(std::fs::read_dir(&runs_dir).unwrap().count(), 50);
+        assert!(runs_dir.join(format!("{run_id}.log")).exists());
+    }
+
+    #[test]
+    fn short_output_is_not_saved() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["run", "--target", session.target(), "--", "seq", "1", "3"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("1\n2\n3\n"));
+
+        assert!(!state_home.path().join("tb").join("runs").exists());
+    }
+
+    #[test]
+    fn unknown_run_and_bad_range_fail() {
+        let session = TestSession::new();
+        let state_home = tempfile::tempdir().unwrap();
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", "nosuchrun"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "No saved output for run 'nosuchrun'.",
+            ));
+
+        session
+            .tb_command()
+            .env("XDG_STATE_HOME", state_home.path())
+            .args(["output", "nosuchrun", "--range", "5:2"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid range '5:2'"));
+    }
+}
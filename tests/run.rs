@@ -370,9 +370,16 @@ mod run_dry_run_shell_quoting {
         )
     }
 
+    fn expected_direct_bash_dry_run(script: &str) -> String {
+        format!(
+            "echo ___START_dryrunid___; {}; echo ___END_dryrunid_$?:\"${{PIPESTATUS[*]}}\"___\n",
+            script
+        )
+    }
+
     fn expected_direct_fish_dry_run(script: &str) -> String {
         format!(
-            "echo ___START_dryrunid___; {}; echo ___END_dryrunid_{{$status}}___\n",
+            "echo ___START_dryrunid___; {}; echo ___END_dryrunid_{{$status}}:\"$pipestatus\"___\n",
             script
         )
     }
@@ -474,7 +481,7 @@ mod run_dry_run_shell_quoting {
             .assert()
             .success()
             .stdout(predicate::eq(
-                expected_direct_posix_dry_run("echo hi").into_bytes(),
+                expected_direct_bash_dry_run("echo hi").into_bytes(),
             ));
     }
 
@@ -795,3 +802,2563 @@ mod run_output_truncation {
             .stdout(predicate::str::contains("truncated").not());
     }
 }
+
+mod run_streaming {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::time::Instant;
+
+    #[test]
+    fn prints_lines_before_command_finishes() {
+        let session = TestSession::new();
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("tb"))
+            .env("TB_TEST_MODE", "1")
+            .env("TB_SESSION_PREFIX", session.session_prefix())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--stream",
+                "--",
+                "echo early; sleep 3; echo late",
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn tb run --stream");
+
+        let started = Instant::now();
+        let mut reader = BufReader::new(child.stdout.take().expect("stdout should be piped"));
+        let mut first_line = String::new();
+        reader
+            .read_line(&mut first_line)
+            .expect("Failed to read streamed line");
+
+        assert_eq!(first_line, "early\n");
+        assert!(
+            started.elapsed() < Duration::from_secs(3),
+            "first line should arrive before the command finishes, took {:?}",
+            started.elapsed()
+        );
+        assert!(
+            child.try_wait().expect("Failed to poll tb run").is_none(),
+            "tb run should still be waiting for the command"
+        );
+
+        let mut rest = String::new();
+        std::io::Read::read_to_string(&mut reader, &mut rest).expect("Failed to read rest");
+        let status = child.wait().expect("Failed to wait for tb run");
+
+        assert!(status.success());
+        assert_eq!(rest, "late\n");
+    }
+
+    #[test]
+    fn preserves_exit_status() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--stream",
+                "--",
+                "echo streamed; exit_code() { return 7; }; exit_code",
+            ])
+            .assert()
+            .failure()
+            .code(7)
+            .stdout(predicate::eq("streamed\n"));
+    }
+
+    #[test]
+    fn prints_truncated_summary_for_long_output() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--stream",
+                "--first",
+                "2",
+                "--last",
+                "2",
+                "--",
+                "seq",
+                "1",
+                "10",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::starts_with(
+                "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n",
+            ))
+            .stdout(predicate::str::contains("--- Summary (10 lines) ---"))
+            .stdout(predicate::str::contains("(6 lines truncated)"));
+    }
+}
+
+mod run_pipe_capture {
+    use super::*;
+
+    #[test]
+    fn returns_exact_output() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--",
+                "echo hello; echo world",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("hello\nworld\n"));
+    }
+
+    #[test]
+    fn keeps_lines_longer_than_the_pane_intact() {
+        let session = TestSession::new();
+        let long_line = "x".repeat(450);
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--",
+                "printf",
+                "%s\\n",
+                &long_line,
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq(format!("{}\n", long_line)));
+    }
+
+    #[test]
+    fn collapses_carriage_return_progress_lines() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--",
+                "printf 'progress 10%%\\rprogress 99%%\\rfinished    \\n'",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("finished\n"));
+    }
+
+    #[test]
+    fn keeps_output_beyond_scrollback_history() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--first",
+                "1",
+                "--last",
+                "1",
+                "--",
+                "seq",
+                "1",
+                "5000",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::starts_with("1\n"))
+            .stdout(predicate::str::contains("(4998 lines truncated)"))
+            .stdout(predicate::str::ends_with("5000\n"));
+    }
+
+    #[test]
+    fn stops_recording_after_run() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--capture",
+                "pipe",
+                "--",
+                "true",
+            ])
+            .assert()
+            .success();
+
+        let output = std::process::Command::new("tmux")
+            .args([
+                "display-message",
+                "-p",
+                "-t",
+                &session.tmux_name(),
+                "#{pane_pipe}",
+            ])
+            .output()
+            .expect("Failed to inspect pane");
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "0");
+    }
+}
+
+mod run_split_stderr {
+    use super::*;
+
+    #[test]
+    fn replays_stderr_on_stderr_and_keeps_stdout_clean() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--split-stderr",
+                "--",
+                "echo '{\"ok\": true}'; echo 'warning: cache miss' >&2",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("{\"ok\": true}\n"))
+            .stderr(predicate::eq("warning: cache miss\n"));
+    }
+
+    #[test]
+    fn preserves_exit_status_with_direct_shell() {
+        let session = TestSession::new();
+        session.enter_shell("bash");
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--split-stderr",
+                "--",
+                "echo partial; echo 'fatal: bad input' >&2; (exit 9)",
+            ])
+            .assert()
+            .failure()
+            .code(9)
+            .stdout(predicate::eq("partial\n"))
+            .stderr(predicate::eq("fatal: bad input\n"));
+    }
+
+    #[test]
+    fn sends_stderr_to_a_private_file_that_is_removed() {
+        let session = TestSession::new();
+        let tmp = tempfile::tempdir().unwrap();
+
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--split-stderr",
+                "--",
+                "stat -L -c %a /dev/stderr",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("600\n"));
+
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--split-stderr",
+                "--timeout",
+                "1",
+                "--",
+                "sleep",
+                "30",
+            ])
+            .timeout(Duration::from_secs(10))
+            .assert()
+            .code(124);
+
+        let left: Vec<_> = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".err"))
+            .collect();
+        assert!(left.is_empty(), "stderr files left behind: {left:?}");
+    }
+
+    #[test]
+    fn dry_run_shows_stderr_side_file_wrapper() {
+        tb_cmd()
+            .args([
+                "run",
+                "--shell",
+                "bash",
+                "--split-stderr",
+                "--dry-run",
+                "--",
+                "make",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq(
+                "echo ___START_dryrunid___; { make; } 2>/tmp/tb-dryrunid.err; __tb_status=$? __tb_pipestatus=\"${PIPESTATUS[*]}\"; echo ___STDERR_dryrunid___; cat /tmp/tb-dryrunid.err 2>/dev/null; echo ___END_dryrunid_${__tb_status}:\"${__tb_pipestatus}\"___; unset __tb_status __tb_pipestatus\n",
+            ));
+    }
+}
+
+mod run_stdin_forwarding {
+    use super::*;
+
+    #[test]
+    fn feeds_piped_bytes_to_command() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--stdin", "--", "sort"])
+            .write_stdin("pear\napple\nfig\n")
+            .assert()
+            .success()
+            .stdout(predicate::eq("apple\nfig\npear\n"));
+    }
+
+    #[test]
+    fn preserves_shell_metacharacters_exactly() {
+        let session = TestSession::new();
+        session.enter_shell("bash");
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--stdin",
+                "--",
+                "cat",
+            ])
+            .write_stdin("it's \"$HOME\" `date` \\n %s\n")
+            .assert()
+            .success()
+            .stdout(predicate::eq("it's \"$HOME\" `date` \\n %s\n"));
+    }
+
+    #[test]
+    fn stages_stdin_in_a_private_file_that_is_removed() {
+        let session = TestSession::new();
+        let tmp = tempfile::tempdir().unwrap();
+
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--stdin",
+                "--",
+                "stat -L -c %a /dev/stdin",
+            ])
+            .write_stdin("secret\n")
+            .assert()
+            .success()
+            .stdout(predicate::eq("600\n"));
+
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--stdin",
+                "--timeout",
+                "1",
+                "--",
+                "sleep",
+                "30",
+            ])
+            .write_stdin("secret\n")
+            .timeout(Duration::from_secs(10))
+            .assert()
+            .code(124);
+
+        let left: Vec<_> = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".in"))
+            .collect();
+        assert!(left.is_empty(), "stdin files left behind: {left:?}");
+    }
+
+    #[test]
+    fn dry_run_reads_stdin_from_side_file() {
+        tb_cmd()
+            .args([
+                "run",
+                "--shell",
+                "sh",
+                "--stdin",
+                "--dry-run",
+                "--",
+                "wc",
+                "-l",
+            ])
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout(predicate::eq(
+                "echo ___START_dryrunid___; { wc -l; } </tmp/tb-dryrunid.in; echo ___END_dryrunid_$?___\n",
+            ));
+    }
+}
+
+mod run_clean_display {
+    use super::*;
+
+    #[test]
+    fn returns_output_and_exit_status() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--display",
+                "clean",
+                "--",
+                "echo hello; echo world; sh -c 'exit 3'",
+            ])
+            .assert()
+            .code(3)
+            .stdout(predicate::eq("hello\nworld\n"));
+    }
+
+    #[test]
+    fn shows_agent_command_instead_of_wrapper_and_markers() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--display",
+                "clean",
+                "--",
+                "echo",
+                "hello",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("hello\n"));
+
+        let pane = common::wait_for_pane_content(
+            &session.tmux_name(),
+            "clean agent command line",
+            Duration::from_secs(5),
+            |content| content.contains("[agent] $ echo hello"),
+        );
+        assert!(
+            !pane.contains("___START_"),
+            "pane shows start marker:\n{pane}"
+        );
+        assert!(!pane.contains("___END_"), "pane shows end marker:\n{pane}");
+        assert!(!pane.contains("printf"), "pane shows wrapper:\n{pane}");
+    }
+
+    #[test]
+    fn dry_run_emits_markers_as_osc_sequences() {
+        tb_cmd()
+            .args([
+                "run",
+                "--dry-run",
+                "--shell",
+                "bash",
+                "--display",
+                "clean",
+                "--",
+                "echo",
+                "hi",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "printf '\\033]tb;___START_dryrunid___\\007'; echo hi; printf '\\033]tb;___END_dryrunid_%s:%s___\\007' $? \"${PIPESTATUS[*]}\"",
+            ));
+    }
+}
+
+mod run_pane_lock {
+    use super::*;
+    use std::process::Stdio;
+
+    fn spawn_run(session: &TestSession, script: &str) -> std::process::Child {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("tb"))
+            .env("TB_TEST_MODE", "1")
+            .env("TB_SESSION_PREFIX", session.session_prefix())
+            .args(["run", "--target", session.target(), "--", script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn tb run")
+    }
+
+    #[test]
+    fn concurrent_runs_queue_and_both_succeed() {
+        let session = TestSession::new();
+
+        let first = spawn_run(&session, "sleep 1; echo first");
+        std::thread::sleep(Duration::from_millis(300));
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo second"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("second\n"))
+            .stderr(predicate::str::contains("Waiting for another tb command"));
+
+        let first = first.wait_with_output().expect("Failed to wait for tb run");
+        assert!(first.status.success());
+        assert_eq!(String::from_utf8_lossy(&first.stdout), "first\n");
+    }
+
+    #[test]
+    fn no_wait_fails_fast_when_pane_is_busy() {
+        let session = TestSession::new();
+
+        let first = spawn_run(&session, "sleep 2; echo first");
+        std::thread::sleep(Duration::from_millis(300));
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--no-wait",
+                "--",
+                "echo second",
+            ])
+            .timeout(Duration::from_secs(1))
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("busy with another tb command"));
+
+        let first = first.wait_with_output().expect("Failed to wait for tb run");
+        assert!(first.status.success());
+        assert_eq!(String::from_utf8_lossy(&first.stdout), "first\n");
+    }
+
+    #[test]
+    fn keeps_locks_in_a_private_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let session = TestSession::new();
+        let tmp = tempfile::tempdir().unwrap();
+
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .env_remove("XDG_RUNTIME_DIR")
+            .args(["run", "--target", session.target(), "--", "true"])
+            .assert()
+            .success();
+
+        let lock_dir = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("tb-locks-")
+            })
+            .expect("lock directory should be created");
+        let mode = std::fs::metadata(&lock_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // One that others can write to is not trusted.
+        std::fs::set_permissions(&lock_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        session
+            .tb_command()
+            .env("TMPDIR", tmp.path())
+            .env_remove("XDG_RUNTIME_DIR")
+            .args(["run", "--target", session.target(), "--", "true"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("is not private to this user"));
+    }
+}
+
+mod run_idle_check {
+    use super::*;
+
+    fn wait_for_foreground(session: &TestSession, program: &str) {
+        for _ in 0..50 {
+            let output = std::process::Command::new("tmux")
+                .args([
+                    "display-message",
+                    "-p",
+                    "-t",
+                    &session.tmux_name(),
+                    "#{pane_current_command}",
+                ])
+                .output()
+                .expect("Failed to inspect pane");
+            if String::from_utf8_lossy(&output.stdout).trim() == program {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("{} never reached the foreground", program);
+    }
+
+    #[test]
+    fn refuses_when_pane_is_running_a_command() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        session.send_main_pane_command("sleep 30");
+        wait_for_foreground(&session, "sleep");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "hello"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Target pane is running sleep; ask the user to return to a shell",
+            ))
+            .stderr(predicate::str::contains("--force"));
+    }
+
+    #[test]
+    fn refuses_when_a_shell_script_is_reading_input() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        session.send_main_pane_command("bash -c 'read -p \"Your name: \" name; echo \"hi $name\"'");
+        common::wait_for_pane_content(
+            &session.tmux_name(),
+            "script prompt",
+            Duration::from_secs(5),
+            |content| content.lines().any(|line| line.starts_with("Your name:")),
+        );
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "hello"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Target pane is running bash; ask the user to return to a shell",
+            ));
+
+        let content = common::capture_pane_content(&session.tmux_name());
+        assert!(
+            !content.contains("___START_"),
+            "wrapper was typed into the script: {}",
+            content
+        );
+    }
+
+    #[test]
+    fn force_types_into_non_shell_program() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+
+        // A POSIX shell under an unrecognised name stands in for a REPL that
+        // happens to accept shell syntax.
+        let dir = std::env::temp_dir().join(format!("tb-idle-{}", session.id));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        let runner = dir.join("tbrunner");
+        let _ = std::fs::remove_file(&runner);
+        std::os::unix::fs::symlink("/bin/sh", &runner).expect("Failed to link runner");
+        session.send_main_pane_command(&runner.display().to_string());
+        wait_for_foreground(&session, "tbrunner");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "forced"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Target pane is running tbrunner"));
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--force",
+                "--",
+                "echo",
+                "forced",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("forced\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+mod run_pending_input {
+    use super::*;
+
+    fn type_without_enter(session: &TestSession, text: &str) {
+        std::process::Command::new("tmux")
+            .args(["send-keys", "-t", &session.tmux_name(), "-l", text])
+            .status()
+            .expect("Failed to type into pane");
+        common::wait_for_pane_content(
+            &session.tmux_name(),
+            "typed text",
+            Duration::from_secs(5),
+            |content| content.contains(text),
+        );
+    }
+
+    fn cursor_line(session: &TestSession) -> String {
+        let output = std::process::Command::new("tmux")
+            .args([
+                "display-message",
+                "-p",
+                "-t",
+                &session.tmux_name(),
+                "#{cursor_y}",
+            ])
+            .output()
+            .expect("Failed to read cursor");
+        let cursor_y = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let output = std::process::Command::new("tmux")
+            .args([
+                "capture-pane",
+                "-p",
+                "-t",
+                &session.tmux_name(),
+                "-S",
+                &cursor_y,
+                "-E",
+                &cursor_y,
+            ])
+            .output()
+            .expect("Failed to capture cursor line");
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn restores_half_typed_command_after_run() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        type_without_enter(&session, "echo human");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "agent"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("agent\n"));
+
+        let mut line = String::new();
+        for _ in 0..50 {
+            line = cursor_line(&session);
+            if line.ends_with("# echo human") || line.ends_with("$ echo human") {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("pending input was not restored, cursor line: {:?}", line);
+    }
+
+    #[test]
+    fn runs_while_prompt_is_slow_to_draw() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        session.send_main_pane_command(
+            "PROMPT_COMMAND='SECONDS=0; while ((SECONDS < 3)); do :; done'",
+        );
+        session.wait_for_shell_ready();
+
+        // The first run ends before the shell has drawn its next prompt, so
+        // the second finds a blank cursor line.
+        for word in ["first", "second"] {
+            session
+                .tb_command()
+                .args(["run", "--target", session.target(), "--", "echo", word])
+                .timeout(Duration::from_secs(20))
+                .assert()
+                .success()
+                .stdout(predicate::eq(format!("{word}\n")));
+        }
+    }
+
+    #[test]
+    fn types_input_back_when_detached_run_finishes() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        type_without_enter(&session, "echo human");
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--max-time",
+                "1",
+                "--on-timeout",
+                "detach",
+                "--",
+                "sleep 3",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stderr(predicate::str::contains("half-typed").not());
+
+        session.wait_for_check_output("t1", |stdout| {
+            stdout.contains("Task t1 finished with exit code 0.")
+        });
+        let mut line = String::new();
+        for _ in 0..50 {
+            line = cursor_line(&session);
+            if line.ends_with("# echo human") || line.ends_with("$ echo human") {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert!(line.ends_with(" echo human"), "cursor line: {line:?}");
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("half-typed").not());
+    }
+
+    fn runs_under_prompt(setup: &str) {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        session.send_main_pane_command(setup);
+        session.wait_for_shell_ready();
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "agent"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("agent\n"));
+    }
+
+    #[test]
+    fn runs_under_prompt_without_trailing_space() {
+        runs_under_prompt("PS1='$'");
+    }
+
+    #[test]
+    fn runs_under_right_prompt() {
+        // Draw "[main]" at the right edge, then the prompt over the start of
+        // the row, as fish_right_prompt or zsh's RPROMPT would.
+        runs_under_prompt("PS1='$ '; PROMPT_COMMAND='printf \"%66s\\r\" \"[main]\"'");
+    }
+
+    #[test]
+    fn refuses_when_line_editor_cannot_cut_input() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        session.send_main_pane_command(
+            "bind 'set bind-tty-special-chars off'; bind -r '\\C-u'; stty kill undef",
+        );
+        session.wait_for_shell_ready();
+        type_without_enter(&session, "echo human");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "agent"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "half-typed command line that tb could not set aside",
+            ));
+
+        assert!(cursor_line(&session).ends_with("echo human"));
+    }
+}
+
+mod run_pane_modes {
+    use super::*;
+
+    fn enter_mode(session: &TestSession, mode: &str) {
+        let status = std::process::Command::new("tmux")
+            .args([mode, "-t", &session.tmux_name()])
+            .status()
+            .expect("Failed to enter pane mode");
+        assert!(status.success(), "Failed to enter {}", mode);
+    }
+
+    #[test]
+    fn leaves_copy_mode_and_runs() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        enter_mode(&session, "copy-mode");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "hello"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("hello\n"))
+            .stderr(predicate::str::contains("left tmux copy-mode"));
+    }
+
+    #[test]
+    fn refuses_in_other_pane_modes() {
+        let session = TestSession::new();
+        session.wait_for_shell_ready();
+        enter_mode(&session, "clock-mode");
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "hello"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Target pane is in tmux clock-mode",
+            ))
+            .stderr(predicate::str::contains("Ask the user to leave it"));
+    }
+}
+
+mod run_kill_sequence {
+    use super::*;
+
+    fn run_until_timeout(session: &TestSession, kill_sequence: &str, script: &str) -> String {
+        let output = session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--timeout",
+                "1",
+                "--kill-sequence",
+                kill_sequence,
+                "--",
+                script,
+            ])
+            .timeout(Duration::from_secs(20))
+            .assert()
+            .code(124)
+            .get_output()
+            .stderr
+            .clone();
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    fn assert_shell_survived(session: &TestSession) {
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "alive"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("alive\n"));
+    }
+
+    #[test]
+    fn reports_interrupt_that_stopped_command() {
+        let session = TestSession::new();
+
+        let stderr = run_until_timeout(&session, "INT:3,QUIT:3", "sleep 30");
+
+        assert!(
+            stderr.contains("Stopped the command with SIGINT."),
+            "{stderr}"
+        );
+        assert_shell_survived(&session);
+    }
+
+    #[test]
+    fn escalates_to_term_for_process_group_ignoring_keys() {
+        let session = TestSession::new();
+
+        let stderr = run_until_timeout(
+            &session,
+            "INT:1,QUIT:1,TERM:3",
+            "sh -c 'trap \"\" INT QUIT; sleep 30'",
+        );
+
+        assert!(
+            stderr.contains("Stopped the command with SIGTERM."),
+            "{stderr}"
+        );
+        assert_shell_survived(&session);
+    }
+
+    #[test]
+    fn escalates_to_kill_when_everything_else_is_ignored() {
+        let session = TestSession::new();
+
+        let stderr = run_until_timeout(
+            &session,
+            "INT:1,TERM:1,KILL",
+            "sh -c 'trap \"\" INT QUIT TERM; sleep 30'",
+        );
+
+        assert!(
+            stderr.contains("Stopped the command with SIGKILL."),
+            "{stderr}"
+        );
+        assert_shell_survived(&session);
+    }
+
+    #[test]
+    fn rejects_unknown_signals() {
+        tb_cmd()
+            .args([
+                "run",
+                "--kill-sequence",
+                "INT:3,STOP:1",
+                "--dry-run",
+                "--",
+                "true",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("unknown signal 'STOP'"));
+    }
+}
+
+mod run_timeout_output {
+    use super::*;
+
+    #[test]
+    fn no_output_timeout_prints_partial_output_and_screen() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--timeout",
+                "1",
+                "--",
+                "echo started; echo waiting; sleep 30",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stdout(predicate::eq("started\nwaiting\n"))
+            .stderr(predicate::str::contains(
+                "Timeout: no output for 1 seconds.",
+            ))
+            .stderr(predicate::str::contains("Screen before the interrupt:\n"))
+            .stderr(predicate::str::contains("waiting"))
+            .stderr(predicate::str::contains("^C").not());
+    }
+
+    #[test]
+    fn max_time_prints_partial_output() {
+        let session = TestSession::new();
+
+        let output = session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--max-time",
+                "2",
+                "--",
+                "while true; do echo tick; sleep 0.5; done",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stderr(predicate::str::contains(
+                "Timeout: max-time of 2 seconds exceeded.",
+            ))
+            .stderr(predicate::str::contains("Screen before the interrupt:"))
+            .get_output()
+            .stdout
+            .clone();
+
+        let stdout = String::from_utf8_lossy(&output);
+        assert!(stdout.starts_with("tick\n"), "stdout: {stdout:?}");
+        assert!(
+            stdout.lines().all(|line| line == "tick"),
+            "stdout: {stdout:?}"
+        );
+    }
+}
+
+mod run_detach_on_timeout {
+    use super::*;
+
+    #[test]
+    fn follows_detached_command_through_check_and_done() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--max-time",
+                "1",
+                "--on-timeout",
+                "detach",
+                "--",
+                "echo begin; sleep 3; echo end; sh -c 'exit 4'",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stdout(predicate::str::starts_with("begin\n"))
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ))
+            .stdout(predicate::str::contains(format!(
+                "tb check --target {} t1",
+                session.target()
+            )))
+            .stderr(predicate::str::contains(
+                "Timeout: max-time of 1 seconds exceeded.",
+            ))
+            .stderr(predicate::str::contains("Stopped the command").not());
+
+        let check = session.wait_for_check_output("t1", |stdout| {
+            stdout.contains("Task t1 finished with exit code 4.")
+        });
+        assert!(check.starts_with("begin\nend\n"), "check output: {check}");
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Released task t1"));
+
+        assert_eq!(session.wait_for_pane_count(1), 1);
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "again"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("again\n"));
+    }
+
+    #[test]
+    fn done_refuses_while_detached_command_runs() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--max-time",
+                "1",
+                "--on-timeout",
+                "detach",
+                "--",
+                "sleep 30",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ));
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Task t1 is still running"));
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "blocked"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Target pane is running sleep"));
+    }
+
+    fn detach_sleep(session: &TestSession) {
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--max-time",
+                "1",
+                "--on-timeout",
+                "detach",
+                "--",
+                "sleep 30",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(124)
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ));
+    }
+
+    #[test]
+    fn done_releases_a_command_the_user_interrupted() {
+        let session = TestSession::new();
+        detach_sleep(&session);
+
+        std::process::Command::new("tmux")
+            .args(["send-keys", "-t", &session.tmux_name(), "C-c"])
+            .status()
+            .expect("Failed to interrupt");
+        session.wait_for_check_output("t1", |stdout| {
+            stdout.contains("Task t1 stopped without an exit code")
+        });
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Released task t1"));
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", "again"])
+            .assert()
+            .success()
+            .stdout(predicate::eq("again\n"));
+    }
+
+    #[test]
+    fn done_force_releases_a_running_command() {
+        let session = TestSession::new();
+        detach_sleep(&session);
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("tb done --force"));
+        session
+            .tb_command()
+            .args(["done", "--force", "--target", session.target(), "t1"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Released task t1"));
+        session
+            .tb_command()
+            .args(["check", "--target", session.target(), "t1"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("t1"));
+    }
+}
+
+mod run_json_output {
+    use super::*;
+
+    fn run_json(session: &TestSession, args: &[&str], code: i32) -> serde_json::Value {
+        let assert = session
+            .tb_command()
+            .args(["run", "--json"])
+            .args(args)
+            .timeout(Duration::from_secs(20))
+            .assert()
+            .code(code);
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+        serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("{e}: {stdout}"))
+    }
+
+    #[test]
+    fn reports_output_exit_code_and_pane() {
+        let session = TestSession::new();
+
+        let report = run_json(
+            &session,
+            &[
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "printf '%s\\n' 'a \"quoted\" back\\slash'; (exit 3)",
+            ],
+            3,
+        );
+
+        assert_eq!(report["output"], "a \"quoted\" back\\slash");
+        assert_eq!(report["exit_code"], 3);
+        assert_eq!(report["timed_out"], false);
+        assert_eq!(report["needs_human"], false);
+        assert!(report["timeout"].is_null());
+        assert_eq!(report["truncated_lines"], 0);
+        assert_eq!(report["shell"], "bash");
+        assert!(report["duration_ms"].is_u64());
+        assert!(
+            report["pane_id"]
+                .as_str()
+                .is_some_and(|id| id.starts_with('%')),
+            "report: {report}"
+        );
+    }
+
+    #[test]
+    fn keeps_output_window_and_counts_truncated_lines() {
+        let session = TestSession::new();
+
+        let report = run_json(
+            &session,
+            &[
+                "--target",
+                session.target(),
+                "--first",
+                "2",
+                "--last",
+                "1",
+                "--",
+                "seq 1 10",
+            ],
+            0,
+        );
+
+        assert_eq!(report["output"], "1\n2\n10");
+        assert_eq!(report["truncated_lines"], 7);
+        assert_eq!(report["exit_code"], 0);
+    }
+
+    #[test]
+    fn tells_own_exit_code_125_from_waiting_for_the_human() {
+        let session = TestSession::new();
+
+        let report = run_json(
+            &session,
+            &["--target", session.target(), "--", "sh -c 'exit 125'"],
+            125,
+        );
+
+        assert_eq!(report["exit_code"], 125);
+        assert_eq!(report["needs_human"], false);
+        assert!(report["waiting_for"].is_null());
+    }
+
+    #[test]
+    fn reports_which_timeout_fired() {
+        let session = TestSession::new();
+
+        let report = run_json(
+            &session,
+            &[
+                "--target",
+                session.target(),
+                "--timeout",
+                "1",
+                "--",
+                "echo partial; sleep 30",
+            ],
+            124,
+        );
+
+        assert_eq!(report["output"], "partial");
+        assert_eq!(report["timed_out"], true);
+        assert_eq!(report["timeout"], "no_output");
+        assert!(report["exit_code"].is_null());
+        assert_eq!(report["stopped_by"], "INT");
+    }
+
+    #[test]
+    fn reports_errors_as_json() {
+        let session = TestSession::new();
+
+        let report = run_json(
+            &session,
+            &["--target", "tb-json-missing-target", "--", "echo", "hi"],
+            1,
+        );
+
+        let error = report["error"].as_str().unwrap_or_default();
+        assert!(error.contains("not found"), "report: {report}");
+        assert!(report["exit_code"].is_null(), "report: {report}");
+        assert!(report["pane_id"].is_null(), "report: {report}");
+
+        // An error carries every field a result does.
+        let result = run_json(&session, &["--target", session.target(), "--", "true"], 0);
+        let mut keys: Vec<&str> = result
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.push("error");
+        let mut error_keys: Vec<&str> = report
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        error_keys.sort();
+        assert_eq!(error_keys, keys);
+    }
+
+    #[test]
+    fn escapes_control_characters_and_keeps_other_text() {
+        let session = TestSession::new();
+        let target = "tb-json-\u{1}\t\u{1b}[31m\"quoted\"\\-😀-ü";
+
+        let report = run_json(&session, &["--target", target, "--", "echo", "hi"], 1);
+        let error = report["error"].as_str().unwrap_or_default();
+        assert!(
+            error.contains(&format!("Target '{target}' not found.")),
+            "report: {report}"
+        );
+
+        let report = run_json(
+            &session,
+            &["--target", session.target(), "--", "echo 'naïve 😀 𝄞'"],
+            0,
+        );
+        assert_eq!(report["output"], "naïve 😀 𝄞");
+    }
+}
+
+mod run_ansi_output {
+    use super::*;
+
+    const COLORED: &str = "printf '\\033[31mred\\033[0m <plain> & more\\n'";
+
+    fn run_ansi(session: &TestSession, args: &[&str]) -> String {
+        let assert = session
+            .tb_command()
+            .args(["run", "--target", session.target()])
+            .args(args)
+            .args(["--", COLORED])
+            .assert()
+            .success();
+        String::from_utf8_lossy(&assert.get_output().stdout).to_string()
+    }
+
+    #[test]
+    fn strips_colors_by_default() {
+        let session = TestSession::new();
+
+        assert_eq!(run_ansi(&session, &[]), "red <plain> & more\n");
+        assert_eq!(
+            run_ansi(&session, &["--capture", "pipe"]),
+            "red <plain> & more\n"
+        );
+    }
+
+    #[test]
+    fn keep_preserves_sgr_sequences() {
+        let session = TestSession::new();
+
+        for args in [
+            &["--ansi", "keep"][..],
+            &["--ansi", "keep", "--capture", "pipe"],
+        ] {
+            let stdout = run_ansi(&session, args);
+            assert!(stdout.contains("\x1b[31mred"), "{args:?}: {stdout:?}");
+            assert!(!stdout.contains("___"), "{args:?}: {stdout:?}");
+            assert!(stdout.contains(" <plain> & more"), "{args:?}: {stdout:?}");
+        }
+    }
+
+    #[test]
+    fn html_renders_colored_spans() {
+        let session = TestSession::new();
+
+        let stdout = run_ansi(&session, &["--ansi", "html"]);
+        assert_eq!(
+            stdout,
+            "<pre class=\"tb-output\">\n\
+             <span style=\"color:#cd0000\">red</span> &lt;plain&gt; &amp; more\n\
+             </pre>\n"
+        );
+    }
+
+    #[test]
+    fn html_refuses_stream() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--ansi",
+                "html",
+                "--stream",
+                "--",
+                "echo",
+                "hi",
+            ])
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains(
+                "the argument '--ansi html' cannot be used with '--stream'",
+            ));
+    }
+}
+
+mod run_wrapped_lines {
+    use super::*;
+
+    fn narrow_session() -> TestSession {
+        let session = TestSession::new();
+        let status = std::process::Command::new("tmux")
+            .args(["resize-window", "-t", &session.tmux_name(), "-x", "30"])
+            .status()
+            .expect("Failed to resize window");
+        assert!(status.success());
+        session
+    }
+
+    fn long_line() -> String {
+        (0..20).map(|n| format!("item-{n:02};")).collect()
+    }
+
+    #[test]
+    fn joins_lines_the_pane_wrapped() {
+        let session = narrow_session();
+        let line = long_line();
+
+        for stream in [false, true] {
+            let mut command = session.tb_command();
+            command.args(["run", "--target", session.target()]);
+            if stream {
+                command.arg("--stream");
+            }
+            command
+                .args(["--", "printf", "%s\\n", &line, "next"])
+                .assert()
+                .success()
+                .stdout(predicate::eq(format!("{line}\nnext\n")));
+        }
+    }
+}
+
+mod run_isolation {
+    use super::*;
+
+    fn run_bash(session: &TestSession, args: &[&str], script: &str) -> String {
+        let assert = session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--shell", "bash"])
+            .args(args)
+            .args(["--", script])
+            .assert()
+            .success();
+        String::from_utf8_lossy(&assert.get_output().stdout).to_string()
+    }
+
+    #[test]
+    fn isolate_keeps_cd_and_export_out_of_the_shell() {
+        let session = TestSession::new();
+        let probe = r#"echo "$PWD ${TB_LEAK:-unset}""#;
+        let before = run_bash(&session, &[], probe);
+
+        assert_eq!(
+            run_bash(
+                &session,
+                &["--isolate"],
+                "cd /; export TB_LEAK=1; echo $PWD"
+            ),
+            "/\n"
+        );
+        assert_eq!(run_bash(&session, &[], probe), before);
+        assert!(before.ends_with(" unset\n"), "before: {before}");
+    }
+
+    #[test]
+    fn cwd_and_env_apply_to_the_command_only() {
+        let session = TestSession::new();
+        let probe = r#"echo "$PWD ${TB_GREETING:-unset}""#;
+        let before = run_bash(&session, &[], probe);
+
+        assert_eq!(
+            run_bash(
+                &session,
+                &["--cwd", "/tmp", "--env", "TB_GREETING=it's $HOME"],
+                probe
+            ),
+            "/tmp it's $HOME\n"
+        );
+        assert_eq!(run_bash(&session, &[], probe), before);
+
+        // The sh -c fallback gets the same treatment.
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--cwd",
+                "/",
+                "--env",
+                "TB_GREETING=hi",
+                "--",
+                probe,
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("/ hi\n"));
+    }
+
+    #[test]
+    fn missing_cwd_fails_without_running_the_command() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--cwd",
+                "/nonexistent-tb-dir",
+                "--",
+                "echo ran",
+            ])
+            .assert()
+            .code(1)
+            .stdout(predicate::str::contains("ran").not());
+    }
+
+    #[test]
+    fn fish_runs_isolated_commands_in_a_child_fish() {
+        tb_cmd()
+            .args([
+                "run",
+                "--dry-run",
+                "--shell",
+                "fish",
+                "--cwd",
+                "/tmp",
+                "--env",
+                "A=it's",
+                "--",
+                "pwd",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                r"fish -c 'cd \'/tmp\'; or exit; set -gx A \'it\\\'s\'; pwd; ",
+            ));
+    }
+
+    #[test]
+    fn rejects_malformed_env() {
+        tb_cmd()
+            .args(["run", "--dry-run", "--env", "NOVALUE", "--", "true"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("use KEY=VALUE"));
+    }
+}
+
+mod run_exit_statuses {
+    use super::*;
+
+    #[test]
+    fn reports_signal_deaths() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "sh -c 'kill -SEGV $$'",
+            ])
+            .assert()
+            .code(139)
+            .stderr(predicate::str::contains(
+                "Command terminated by SIGSEGV (exit code 139).",
+            ));
+    }
+
+    #[test]
+    fn reports_failed_pipeline_stages() {
+        let session = TestSession::new();
+
+        for args in [&[][..], &["--split-stderr"], &["--display", "clean"]] {
+            session
+                .tb_command()
+                .args(["run", "--target", session.target(), "--shell", "bash"])
+                .args(args)
+                .args(["--", "true | (exit 3) | cat"])
+                .assert()
+                .success()
+                .stderr(predicate::str::contains(
+                    "Pipeline stage statuses: 0 | 3 | 0",
+                ));
+        }
+    }
+
+    #[test]
+    fn leaves_no_saved_statuses_in_the_shell() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--shell", "bash"])
+            .args(["--split-stderr", "--", "true | (exit 3)"])
+            .assert()
+            .code(3);
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--shell", "bash"])
+            .args(["--", "echo \"[${__tb_status-}${__tb_pipestatus-}]\""])
+            .assert()
+            .success()
+            .stdout(predicate::eq("[]\n"));
+    }
+
+    #[test]
+    fn pipefail_fails_with_last_failed_stage() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--pipefail",
+                "--",
+                "(exit 2) | (exit 3) | true",
+            ])
+            .assert()
+            .code(3);
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--pipefail",
+                "--",
+                "echo ok | cat",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("ok\n"))
+            .stderr(predicate::str::contains("Pipeline").not());
+    }
+
+    #[test]
+    fn pipefail_reaches_into_isolated_subshell() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--pipefail",
+                "--cwd",
+                "/tmp",
+                "--",
+                "(exit 2) | true",
+            ])
+            .assert()
+            .code(2);
+    }
+
+    #[test]
+    fn isolated_command_reports_pipeline_stages() {
+        let session = TestSession::new();
+
+        let assert = session
+            .tb_command()
+            .args([
+                "run",
+                "--json",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--isolate",
+                "--pipefail",
+                "--",
+                "cd /; (exit 2) | (exit 3) | true",
+            ])
+            .assert()
+            .code(3);
+        let report: serde_json::Value =
+            serde_json::from_slice(&assert.get_output().stdout).expect("stdout should be JSON");
+        assert_eq!(report["exit_code"], 0);
+        assert_eq!(report["pipestatus"], serde_json::json!([2, 3, 0]));
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--isolate",
+                "--",
+                "true | (exit 4) | true",
+            ])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "Pipeline stage statuses: 0 | 4 | 0",
+            ));
+    }
+
+    #[test]
+    fn pipefail_needs_bash_or_fish() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--pipefail",
+                "--",
+                "(exit 2) | true",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--pipefail needs bash or fish"));
+    }
+
+    #[test]
+    fn json_includes_pipestatus_and_signal() {
+        let session = TestSession::new();
+
+        let assert = session
+            .tb_command()
+            .args([
+                "run",
+                "--json",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "true | sh -c 'kill -TERM $$'",
+            ])
+            .assert()
+            .code(143);
+        let report: serde_json::Value =
+            serde_json::from_slice(&assert.get_output().stdout).expect("stdout should be JSON");
+
+        assert_eq!(report["exit_code"], 143);
+        assert_eq!(report["signal"], "SIGTERM");
+        assert_eq!(report["pipestatus"], serde_json::json!([0, 143]));
+    }
+}
+
+mod run_prompt_detection {
+    use super::*;
+
+    #[test]
+    fn stops_at_password_prompt_without_interrupting() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "read -p 'Password: ' secret; echo got-$secret",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125)
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ))
+            .stderr(predicate::str::contains(
+                "Command is waiting at a prompt: Password:",
+            ))
+            .stderr(predicate::str::contains("Stopped the command").not());
+
+        session.send_main_pane_command("hunter2");
+        let check = session.wait_for_check_output("t1", |stdout| {
+            stdout.contains("Task t1 finished with exit code 0.")
+        });
+        assert!(check.contains("got-hunter2"), "check output: {check}");
+    }
+
+    #[test]
+    fn reports_confirmation_prompt_in_json() {
+        let session = TestSession::new();
+
+        let assert = session
+            .tb_command()
+            .args([
+                "run",
+                "--json",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "printf 'Proceed? [Y/n] '; read answer",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125);
+        let report: serde_json::Value =
+            serde_json::from_slice(&assert.get_output().stdout).expect("stdout should be JSON");
+
+        assert_eq!(report["prompt"], "Proceed? [Y/n]");
+        assert_eq!(report["needs_human"], true);
+        assert_eq!(report["task"], "t1");
+        assert_eq!(report["timed_out"], false);
+        assert_eq!(report["exit_code"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn launched_tasks_leave_the_waiting_pane_alone() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "read -p 'Password: ' secret; echo got-$secret",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125)
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ));
+
+        // The human's pane keeps t1, so a launch takes t2 and closing it
+        // leaves the human's pane open and still waiting.
+        assert_eq!(session.launch_task(&["sleep", "30"]), "t2");
+        assert_eq!(session.wait_for_pane_count(2), 2);
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t2"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Closed task t2."));
+        assert_eq!(session.wait_for_pane_count(1), 1);
+
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Task t1 is still running in the user's pane.",
+            ));
+        assert_eq!(session.wait_for_pane_count(1), 1);
+
+        session.send_main_pane_command("hunter2");
+        session.wait_for_check_output("t1", |stdout| {
+            stdout.contains("Task t1 finished with exit code 0.")
+        });
+        session
+            .tb_command()
+            .args(["done", "--target", session.target(), "t1"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Released task t1"));
+        assert_eq!(session.wait_for_pane_count(1), 1);
+    }
+
+    #[test]
+    fn configured_patterns_extend_the_builtin_ones() {
+        let session = TestSession::new();
+        let config_home = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(config_home.path().join("tb")).unwrap();
+        std::fs::write(
+            config_home.path().join("tb/prompt-patterns"),
+            "# OTP from the authenticator app\n(?i)one-time code:\\s*$\n",
+        )
+        .unwrap();
+
+        session
+            .tb_command()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "printf 'One-time code: '; read code",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125)
+            .stderr(predicate::str::contains(
+                "Command is waiting at a prompt: One-time code:",
+            ));
+    }
+
+    #[test]
+    fn rejects_invalid_configured_pattern() {
+        let session = TestSession::new();
+        let config_home = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(config_home.path().join("tb")).unwrap();
+        std::fs::write(config_home.path().join("tb/prompt-patterns"), "(\n").unwrap();
+
+        session
+            .tb_command()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .args(["run", "--target", session.target(), "--", "echo", "hi"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "prompt-patterns line 1: invalid pattern",
+            ));
+    }
+
+    #[test]
+    fn output_mentioning_a_password_is_not_a_prompt() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "echo 'password: updated'; sleep 1; echo finished",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("password: updated\nfinished\n"));
+    }
+}
+
+mod run_human_actions {
+    use super::*;
+
+    #[test]
+    fn lists_sign_in_link_and_code() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "echo 'To sign in, use a web browser to open the page https://microsoft.com/devicelogin and enter the code F4KEC0DE9 to authenticate.'",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "Action needed by human:\n  Open https://microsoft.com/devicelogin\n  Enter code F4KEC0DE9\n",
+            ));
+    }
+
+    #[test]
+    fn stops_waiting_at_device_sign_in() {
+        let session = TestSession::new();
+
+        let assert = session
+            .tb_command()
+            .args([
+                "run",
+                "--json",
+                "--target",
+                session.target(),
+                "--",
+                "printf '%s\\n\\n' 'open the following URL:' 'https://device.sso.us-east-1.amazonaws.com/' 'Then enter the code:' 'ABCD-EFGH'; sleep 30",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125);
+        let report: serde_json::Value =
+            serde_json::from_slice(&assert.get_output().stdout).expect("stdout should be JSON");
+
+        assert_eq!(
+            report["action_needed"],
+            serde_json::json!({
+                "urls": ["https://device.sso.us-east-1.amazonaws.com/"],
+                "codes": ["ABCD-EFGH"],
+            })
+        );
+        assert_eq!(report["prompt"], serde_json::Value::Null);
+        assert_eq!(report["task"], "t1");
+    }
+
+    #[test]
+    fn lists_code_from_gh_auth_login() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "printf '%s\\n' '! First copy your one-time code: 1A2B-3C4D' 'Press Enter to open github.com in your browser... '",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "Action needed by human:\n  Enter code 1A2B-3C4D\n",
+            ));
+    }
+
+    #[test]
+    fn ignores_error_codes_and_author_links() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "printf '%s\\n' 'Error code ABCDEF1 in module' 'Contact the author at https://example.com/about'",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Action needed").not());
+    }
+
+    #[test]
+    fn ignores_links_unrelated_to_sign_in() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "echo 'See https://docs.rs/regex for details, error code E0308'",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Action needed").not());
+    }
+}
+
+mod run_env_profile {
+    use super::*;
+
+    #[test]
+    fn dry_run_shows_noninteractive_profile() {
+        tb_cmd()
+            .args([
+                "run",
+                "--dry-run",
+                "--shell",
+                "bash",
+                "--isolate",
+                "--profile",
+                "noninteractive",
+                "--",
+                "git log",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "( export PAGER=cat; export GIT_PAGER=cat; export SYSTEMD_PAGER=\"\"; export MANPAGER=cat; export LESS=-FRX; export NO_COLOR=1; export DEBIAN_FRONTEND=noninteractive; git log; ",
+            ));
+    }
+
+    #[test]
+    fn profile_applies_to_one_command_only() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--isolate",
+                "--profile",
+                "noninteractive",
+                "--env",
+                "PAGER=more",
+                "--",
+                "echo \"$PAGER|$GIT_PAGER|$NO_COLOR\"",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("more|cat|1\n"));
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "echo \"[$GIT_PAGER]\"",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("[]\n"));
+    }
+
+    #[test]
+    fn profile_file_changes_and_drops_variables() {
+        let session = TestSession::new();
+        let config_home = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(config_home.path().join("tb/profiles")).unwrap();
+        std::fs::write(
+            config_home.path().join("tb/profiles/ci"),
+            "# keep colors for this one\n-NO_COLOR\nPAGER=less\nCI=true\n",
+        )
+        .unwrap();
+
+        session
+            .tb_command()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--isolate",
+                "--profile",
+                "ci",
+                "--",
+                "echo \"$PAGER|$NO_COLOR|$CI|$GIT_PAGER\"",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("less||true|cat\n"));
+    }
+
+    #[test]
+    fn profile_needs_isolate() {
+        tb_cmd()
+            .args([
+                "run",
+                "--dry-run",
+                "--profile",
+                "noninteractive",
+                "--",
+                "git log",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--isolate"));
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        let config_home = tempfile::tempdir().unwrap();
+
+        tb_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .args([
+                "run",
+                "--dry-run",
+                "--isolate",
+                "--profile",
+                "nope",
+                "--",
+                "true",
+            ])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Profile 'nope' not found."));
+    }
+}
+
+mod run_screen_takeover {
+    use super::*;
+
+    #[test]
+    fn stops_when_a_pager_opens() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "seq 1 500 | less",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125)
+            .stdout(predicate::str::contains(
+                "Command still running as task t1.",
+            ))
+            .stderr(predicate::str::contains(
+                "Command opened the pager less, which waits for keys.",
+            ))
+            .stderr(predicate::str::contains("Screen now:"))
+            .stderr(predicate::str::contains("Stopped the command").not());
+    }
+
+    #[test]
+    fn quit_pager_types_q_and_keeps_waiting() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--quit-pager",
+                "--",
+                "seq 1 500 | less; echo after-pager",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("after-pager"));
+    }
+
+    #[test]
+    fn finds_the_pager_behind_the_default_sh_wrapper() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--",
+                "seq 1 500 | less",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125)
+            .stderr(predicate::str::contains(
+                "Command opened the pager less, which waits for keys.",
+            ));
+
+        let session = TestSession::new();
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--quit-pager",
+                "--",
+                "seq 1 500 | less; echo after-pager",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("after-pager"));
+    }
+
+    #[test]
+    fn reports_full_screen_program_in_json() {
+        let session = TestSession::new();
+
+        let assert = session
+            .tb_command()
+            .args([
+                "run",
+                "--json",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "printf '\\033[?1049h'; sleep 30",
+            ])
+            .timeout(Duration::from_secs(15))
+            .assert()
+            .code(125);
+        let report: serde_json::Value =
+            serde_json::from_slice(&assert.get_output().stdout).expect("stdout should be JSON");
+
+        assert_eq!(report["waiting_for"], "full_screen");
+        assert_eq!(report["program"], "sleep");
+        assert_eq!(report["task"], "t1");
+    }
+}
+
+mod run_paste_injection {
+    use super::*;
+
+    #[test]
+    fn multi_line_command_runs_as_one_paste() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "for word in one two; do\n  echo $word\ndone",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("one\ntwo\n"));
+    }
+
+    #[test]
+    fn literal_tab_is_not_completed() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "run",
+                "--target",
+                session.target(),
+                "--shell",
+                "bash",
+                "--",
+                "printf '%s' 'a\tb' | wc -c",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::eq("3\n"));
+    }
+
+    #[test]
+    fn long_command_is_pasted_and_buffer_removed() {
+        let session = TestSession::new();
+        let word = "x".repeat(3000);
+
+        session
+            .tb_command()
+            .args(["run", "--target", session.target(), "--", "echo", &word])
+            .assert()
+            .success()
+            .stdout(predicate::eq(format!("{word}\n")));
+
+        let buffers = std::process::Command::new("tmux")
+            .args(["list-buffers", "-F", "#{buffer_name}"])
+            .output()
+            .expect("Failed to list tmux buffers");
+        assert!(
+            !String::from_utf8_lossy(&buffers.stdout).contains("tb-"),
+            "paste buffer left behind"
+        );
+    }
+
+    #[test]
+    fn inject_keys_and_paste_can_be_forced() {
+        let session = TestSession::new();
+
+        for inject in ["keys", "paste"] {
+            session
+                .tb_command()
+                .args([
+                    "run",
+                    "--target",
+                    session.target(),
+                    "--inject",
+                    inject,
+                    "--",
+                    "echo",
+                    inject,
+                ])
+                .assert()
+                .success()
+                .stdout(predicate::eq(format!("{inject}\n")));
+        }
+    }
+}
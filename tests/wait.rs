@@ -0,0 +1,214 @@
+//! End-to-end black-box tests for `tb wait`
+//!
+//! These tests verify waiting for a pattern, its disappearance, or quiet
+//! output in the main pane and in tasks.
+
+mod common;
+
+use common::TestSession;
+use predicates::prelude::*;
+
+mod wait_for_pattern {
+    use super::*;
+
+    #[test]
+    fn prints_matching_line_once_it_appears() {
+        let session = TestSession::new();
+        session.send_main_pane_command("sleep 1; echo 'Listening on :'3000");
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--pattern",
+                r"Listening on :\d+",
+                "--timeout",
+                "10",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Listening on :3000"));
+    }
+
+    #[test]
+    fn waits_on_a_task_pane() {
+        let session = TestSession::new();
+        let task_id = session.launch_task(&["sh", "-c", "sleep 1; echo server-ready; sleep 30"]);
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                &task_id,
+                "--target",
+                session.target(),
+                "--pattern",
+                "server-ready",
+                "--timeout",
+                "10",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("server-ready"));
+    }
+
+    #[test]
+    fn times_out_with_exit_124() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--pattern",
+                "never-printed",
+                "--timeout",
+                "1",
+            ])
+            .assert()
+            .code(124)
+            .stderr(predicate::str::contains(
+                "Timed out after 1s waiting for 'never-printed' to appear.",
+            ));
+    }
+
+    #[test]
+    fn ignores_a_match_left_by_an_earlier_command() {
+        let session = TestSession::new();
+        session.send_main_pane_command("echo 'Listening on :'3000; echo done");
+        session.wait_for_main_check_output(|stdout| stdout.contains("done\n"));
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--pattern",
+                r"Listening on :\d+",
+                "--timeout",
+                "1",
+            ])
+            .assert()
+            .code(124);
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--pattern",
+                r"Listening on :\d+",
+                "--existing",
+                "--timeout",
+                "1",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Listening on :3000"));
+    }
+
+    #[test]
+    fn rejects_invalid_regex() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args(["wait", "--target", session.target(), "--pattern", "("])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Invalid pattern '('"));
+    }
+}
+
+mod wait_for_gone_or_quiet {
+    use super::*;
+
+    #[test]
+    fn gone_waits_for_pattern_to_disappear() {
+        let session = TestSession::new();
+        session.send_main_pane_command("echo busy-1; sleep 2; clear; tmux clear-history");
+        session.wait_for_main_check_output(|stdout| stdout.contains("busy-1\n"));
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--pattern",
+                r"busy-\d",
+                "--gone",
+                "--timeout",
+                "10",
+            ])
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn gone_waits_while_pattern_is_already_on_screen() {
+        let session = TestSession::new();
+        session.send_main_pane_command(
+            "echo Building; echo step 1/2; sleep 3; clear; tmux clear-history",
+        );
+        session.wait_for_main_check_output(|stdout| stdout.contains("step 1/2\n"));
+
+        let wait_gone = |timeout: &str| {
+            session
+                .tb_command()
+                .args([
+                    "wait",
+                    "--target",
+                    session.target(),
+                    "--pattern",
+                    "Building",
+                    "--gone",
+                    "--timeout",
+                    timeout,
+                ])
+                .assert()
+        };
+        wait_gone("1").code(124);
+        wait_gone("10").success();
+    }
+
+    #[test]
+    fn quiet_waits_for_output_to_settle() {
+        let session = TestSession::new();
+        session.send_main_pane_command("for i in 1 2 3; do echo tick-$i; sleep 0.5; done");
+        session.wait_for_main_check_output(|stdout| stdout.contains("tick-1\n"));
+
+        session
+            .tb_command()
+            .args([
+                "wait",
+                "--target",
+                session.target(),
+                "--quiet",
+                "1",
+                "--timeout",
+                "10",
+            ])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("tick-3"));
+    }
+
+    #[test]
+    fn requires_something_to_wait_for() {
+        let session = TestSession::new();
+
+        session
+            .tb_command()
+            .args(["wait", "--target", session.target()])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Nothing to wait for."));
+    }
+}